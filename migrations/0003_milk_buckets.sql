@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS milk_buckets (
+    bucket TEXT PRIMARY KEY,
+    state JSONB,
+    idle_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX IF NOT EXISTS milk_buckets_idle_at ON milk_buckets (idle_at);
@@ -2,7 +2,7 @@ use std::sync::{Arc, Mutex};
 
 use axum::{
     extract::{Path, Query},
-    http::StatusCode,
+    http::{Method, StatusCode},
     response::{IntoResponse, Response},
     routing::{delete, get, post, put},
     Extension, Json, Router,
@@ -13,6 +13,8 @@ use serde::{Deserialize, Serialize};
 use sqlx::prelude::FromRow;
 use uuid::Uuid;
 
+use crate::day9::layer::RateLimit;
+
 #[derive(Deserialize)]
 pub struct QuoteNew {
     pub author: String,
@@ -28,7 +30,16 @@ pub struct Quote {
     version: i32,
 }
 
-pub fn day_19_routes(pool: sqlx::PgPool) -> Router {
+/// Drafting, undoing and removing quotes are rate limited, each with a
+/// quota of its own. `limits` is shared with whatever else the caller rate
+/// limits.
+pub fn day_19_routes(pool: sqlx::PgPool, limits: &RateLimit) -> Router {
+    let quote_writes = limits
+        .layer("quotes")
+        .route(Method::POST, "/draft")
+        .route(Method::PUT, "/undo/:id")
+        .route(Method::DELETE, "/remove/:id");
+
     Router::new()
         .route("/reset", post(reset))
         .route("/cite/:id", get(get_quote_by_id))
@@ -40,6 +51,7 @@ pub fn day_19_routes(pool: sqlx::PgPool) -> Router {
         .layer(Extension(Arc::new(Mutex::new(
             HashMap::<String, u32>::new(),
         ))))
+        .layer(quote_writes)
 }
 
 fn generate_token() -> String {
@@ -1,70 +1,979 @@
-use std::net::Ipv6Addr;
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
 
-use axum::{extract::Query, response::IntoResponse};
-use itertools::Itertools;
-use serde::Deserialize;
+use axum::{
+    body::Bytes,
+    extract::{DefaultBodyLimit, Query},
+    http::{header::CONTENT_TYPE, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use hashbrown::HashMap;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+pub mod pcap;
+
+const DEFAULT_PER_PAGE: u64 = 256;
+const MAX_PER_PAGE: u64 = 4096;
+const BATCH_BODY_LIMIT: usize = 32 * 1024 * 1024;
+const PCAP_BODY_LIMIT: usize = 256 * 1024 * 1024;
+
+pub fn day_2_routes() -> Router {
+    Router::new()
+        .route("/dest", get(dest_2))
+        .route("/key", get(key_2))
+        .route("/v6/dest", get(dest_2_v6))
+        .route("/v6/key", get(key_2_v6))
+        .route(
+            "/batch",
+            post(batch_2).layer(DefaultBodyLimit::max(BATCH_BODY_LIMIT)),
+        )
+        .route("/solve", post(solve_2))
+        .route("/route", get(route_2))
+        .route(
+            "/pcap",
+            post(pcap_2).layer(DefaultBodyLimit::max(PCAP_BODY_LIMIT)),
+        )
+}
 
 #[derive(Deserialize)]
 pub struct Day2Params {
-    from: String,
+    from: Option<String>,
     key: Option<String>,
     to: Option<String>,
+    keys: Option<String>,
+    op: Option<String>,
+    page: Option<u64>,
+    per_page: Option<u64>,
 }
-pub async fn dest_2(params: Query<Day2Params>) -> impl IntoResponse {
-    let params = params.0;
-    let from: Vec<u8> = params
-        .from
-        .split('.')
-        .map(|p| p.parse::<u8>().unwrap())
-        .collect();
-    let key: Vec<u8> = params
-        .key
-        .unwrap()
+
+impl Day2Params {
+    fn paging(&self) -> Paging {
+        Paging {
+            page: self.page.unwrap_or(1).max(1),
+            per_page: self
+                .per_page
+                .unwrap_or(DEFAULT_PER_PAGE)
+                .clamp(1, MAX_PER_PAGE),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Paging {
+    page: u64,
+    per_page: u64,
+}
+
+impl Paging {
+    /// Host indexes covered by this page within a block of `host_bits` bits.
+    fn range(&self, host_bits: u32) -> impl Iterator<Item = u128> {
+        let last = u128::MAX.checked_shr(128 - host_bits).unwrap_or(0);
+        let start = (self.page - 1) as u128 * self.per_page as u128;
+        let end = (start + self.per_page as u128 - 1).min(last);
+
+        (start <= last).then_some(start..=end).into_iter().flatten()
+    }
+}
+
+/// Result of applying the cipher to a whole CIDR block.
+#[derive(Debug, Serialize)]
+pub struct SubnetMapping {
+    pub from: String,
+    /// The transformed block, or `None` when the hosts no longer form a
+    /// single CIDR block (for example `add` with key bits set below a prefix
+    /// that splits an octet, or any `rotl` by a non-zero amount).
+    pub network: Option<String>,
+    pub total_hosts: String,
+    pub page: u64,
+    pub per_page: u64,
+    pub hosts: Vec<HostMapping>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HostMapping {
+    pub from: String,
+    pub to: String,
+}
+
+/// Errors returned by the day 2 address endpoints. Each one names the query
+/// field it came from and renders as a JSON body, with status 400 for
+/// malformed input and 422 for operands whose families cannot be reconciled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Day2Error {
+    MissingParam(&'static str),
+    BadOctet { field: &'static str, octet: String },
+    OctetCount { field: &'static str, count: usize },
+    InvalidIpv6 { field: &'static str, value: String },
+    InvalidPrefix { field: &'static str, value: String },
+    UnexpectedParam(&'static str),
+    MixedFamily(&'static str),
+    InvalidOp(String),
+    InvalidCapture(String),
+}
+
+impl Day2Error {
+    pub fn field(&self) -> &'static str {
+        match self {
+            Day2Error::MissingParam(field)
+            | Day2Error::UnexpectedParam(field)
+            | Day2Error::MixedFamily(field)
+            | Day2Error::BadOctet { field, .. }
+            | Day2Error::OctetCount { field, .. }
+            | Day2Error::InvalidIpv6 { field, .. }
+            | Day2Error::InvalidPrefix { field, .. } => field,
+            Day2Error::InvalidOp(_) => "op",
+            Day2Error::InvalidCapture(_) => "capture",
+        }
+    }
+
+    pub fn reason(&self) -> String {
+        match self {
+            Day2Error::MissingParam(field) => format!("missing {field}"),
+            Day2Error::UnexpectedParam(field) => format!("unexpected {field}"),
+            Day2Error::MixedFamily(field) => {
+                format!("{field} does not match the address family of the other addresses")
+            }
+            Day2Error::BadOctet { octet, .. } => format!("bad octet '{octet}'"),
+            Day2Error::OctetCount { count, .. } => {
+                format!("expected 4 octets, found {count}")
+            }
+            Day2Error::InvalidIpv6 { value, .. } => format!("invalid IPv6 literal '{value}'"),
+            Day2Error::InvalidPrefix { value, .. } => format!("invalid prefix length '{value}'"),
+            Day2Error::InvalidCapture(reason) => reason.clone(),
+            Day2Error::InvalidOp(value) => {
+                format!("unknown op '{value}', expected add, sub, xor or rotl:N")
+            }
+        }
+    }
+}
+
+impl Day2Error {
+    fn status(&self) -> StatusCode {
+        match self {
+            Day2Error::MixedFamily(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    fn body(&self) -> serde_json::Value {
+        json!({ "field": self.field(), "error": self.reason() })
+    }
+}
+
+impl IntoResponse for Day2Error {
+    fn into_response(self) -> Response {
+        (self.status(), Json(self.body())).into_response()
+    }
+}
+
+/// A single parsed address. Dotted IPv4 and IPv4-mapped IPv6 addresses
+/// (`::ffff:a.b.c.d`) are both IPv4 operands; `mapped` records which notation
+/// was used so results can be written back the same way.
+#[derive(Debug, Clone, Copy)]
+enum Operand {
+    V4 { addr: Ipv4Addr, mapped: bool },
+    V6(Ipv6Addr),
+}
+
+/// Two operands normalized into one family.
+#[derive(Debug, Clone, Copy)]
+enum Pair {
+    V4 {
+        from: Ipv4Addr,
+        other: Ipv4Addr,
+        mapped: bool,
+    },
+    V6 {
+        from: Ipv6Addr,
+        other: Ipv6Addr,
+    },
+}
+
+fn parse_addr(field: &'static str, value: &str) -> Result<Operand, Day2Error> {
+    if !value.contains(':') {
+        return Ok(Operand::V4 {
+            addr: parse_v4(field, value)?,
+            mapped: false,
+        });
+    }
+
+    let addr = parse_v6(field, value)?;
+    Ok(match addr.to_ipv4_mapped() {
+        Some(addr) => Operand::V4 { addr, mapped: true },
+        None => Operand::V6(addr),
+    })
+}
+
+/// Parses a key in the family `from` picked. Keys for plain IPv6 addresses
+/// are taken as they are even when they look IPv4-mapped, since XOR-ing two
+/// IPv6 addresses can give one; IPv4 keys may be dotted or mapped.
+fn parse_key(field: &'static str, value: &str, from: Operand) -> Result<Operand, Day2Error> {
+    match from {
+        Operand::V6(_) if value.contains(':') => Ok(Operand::V6(parse_v6(field, value)?)),
+        _ => parse_addr(field, value),
+    }
+}
+
+/// Brings `from` and the second operand (`key` or `to`, named by `field`)
+/// into one family:
+///
+/// - two IPv4 operands, dotted or IPv4-mapped in any combination, use the
+///   per-octet IPv4 cipher on the embedded addresses;
+/// - two plain IPv6 operands use the IPv6 cipher;
+/// - an IPv4 operand paired with a plain IPv6 one is rejected with a 422.
+///
+/// The result is written in the notation of `from`, so a mapped `from` gives
+/// a mapped result. Which route was called does not restrict the family.
+fn normalize(from: Operand, field: &'static str, other: Operand) -> Result<Pair, Day2Error> {
+    match (from, other) {
+        (Operand::V4 { addr: from, mapped }, Operand::V4 { addr: other, .. }) => Ok(Pair::V4 {
+            from,
+            other,
+            mapped,
+        }),
+        (Operand::V6(from), Operand::V6(other)) => Ok(Pair::V6 { from, other }),
+        _ => Err(Day2Error::MixedFamily(field)),
+    }
+}
+
+fn render_v4(addr: Ipv4Addr, mapped: bool) -> String {
+    match mapped {
+        true => addr.to_ipv6_mapped().to_string(),
+        false => addr.to_string(),
+    }
+}
+
+fn render_v4_block(addr: Ipv4Addr, prefix: u8, mapped: bool) -> String {
+    match mapped {
+        true => format!("{}/{}", addr.to_ipv6_mapped(), prefix + 96),
+        false => format!("{addr}/{prefix}"),
+    }
+}
+
+fn required<'a>(field: &'static str, value: &'a Option<String>) -> Result<&'a str, Day2Error> {
+    value.as_deref().ok_or(Day2Error::MissingParam(field))
+}
+
+fn split_cidr(value: &str) -> (&str, Option<&str>) {
+    match value.split_once('/') {
+        Some((addr, prefix)) => (addr, Some(prefix)),
+        None => (value, None),
+    }
+}
+
+fn parse_v4(field: &'static str, value: &str) -> Result<Ipv4Addr, Day2Error> {
+    let octets = value
         .split('.')
-        .map(|p| p.parse::<u8>().unwrap())
-        .collect();
+        .map(|p| {
+            p.parse::<u8>().map_err(|_| Day2Error::BadOctet {
+                field,
+                octet: p.to_string(),
+            })
+        })
+        .collect::<Result<Vec<u8>, _>>()?;
+    let octets: [u8; 4] = octets
+        .try_into()
+        .map_err(|o: Vec<u8>| Day2Error::OctetCount {
+            field,
+            count: o.len(),
+        })?;
 
-    (0..from.len())
-        .map(|i| from[i].wrapping_add(key[i]))
-        .join(".")
+    Ok(Ipv4Addr::from(octets))
 }
-pub async fn dest_2_v6(params: Query<Day2Params>) -> impl IntoResponse {
-    let params = params.0;
-    let from: Ipv6Addr = params.from.parse().unwrap();
-    let key: Ipv6Addr = params.key.unwrap().parse().unwrap();
 
-    let result = from.to_bits() ^ key.to_bits();
+fn parse_v6(field: &'static str, value: &str) -> Result<Ipv6Addr, Day2Error> {
+    value.parse().map_err(|_| Day2Error::InvalidIpv6 {
+        field,
+        value: value.to_string(),
+    })
+}
+
+fn parse_prefix(field: &'static str, value: &str, max: u8) -> Result<u8, Day2Error> {
+    value
+        .parse::<u8>()
+        .ok()
+        .filter(|&p| p <= max)
+        .ok_or_else(|| Day2Error::InvalidPrefix {
+            field,
+            value: value.to_string(),
+        })
+}
+
+fn host_count(host_bits: u32) -> String {
+    match host_bits {
+        // 2^128 does not fit in a u128
+        128 => "340282366920938463463374607431768211456".to_string(),
+        bits => (1u128 << bits).to_string(),
+    }
+}
+
+/// Encrypts an IPv4 address by adding each octet of `key`, wrapping at 256.
+pub fn encrypt_v4(from: Ipv4Addr, key: Ipv4Addr) -> Ipv4Addr {
+    let (from, key) = (from.octets(), key.octets());
+    let result: [u8; 4] = std::array::from_fn(|i| from[i].wrapping_add(key[i]));
+
+    Ipv4Addr::from(result)
+}
+
+/// Encrypts an IPv6 address by XOR-ing it with `key`.
+pub fn encrypt_v6(from: Ipv6Addr, key: Ipv6Addr) -> Ipv6Addr {
+    Ipv6Addr::from(from.to_bits() ^ key.to_bits())
+}
+
+/// Recovers the key that [`encrypt_v4`] would need to turn `from` into `to`.
+pub fn derive_key_v4(from: Ipv4Addr, to: Ipv4Addr) -> Ipv4Addr {
+    let (from, to) = (from.octets(), to.octets());
+    let result: [u8; 4] = std::array::from_fn(|i| to[i].wrapping_sub(from[i]));
+
+    Ipv4Addr::from(result)
+}
+
+/// Recovers the key that [`encrypt_v6`] would need to turn `from` into `to`.
+pub fn derive_key_v6(from: Ipv6Addr, to: Ipv6Addr) -> Ipv6Addr {
+    Ipv6Addr::from(from.to_bits() ^ to.to_bits())
+}
+
+fn zip_octets(a: Ipv4Addr, b: Ipv4Addr, f: fn(u8, u8) -> u8) -> Ipv4Addr {
+    let (a, b) = (a.octets(), b.octets());
+    let result: [u8; 4] = std::array::from_fn(|i| f(a[i], b[i]));
+
+    Ipv4Addr::from(result)
+}
+
+fn zip_segments(a: Ipv6Addr, b: Ipv6Addr, f: fn(u16, u16) -> u16) -> Ipv6Addr {
+    let (a, b) = (a.segments(), b.segments());
+    let result: [u16; 8] = std::array::from_fn(|i| f(a[i], b[i]));
+
+    Ipv6Addr::from(result)
+}
+
+/// Cipher operation selected with the `op` parameter.
+///
+/// - `add`: wrapping add per IPv4 octet or per IPv6 segment (16 bits);
+/// - `sub`: wrapping subtract, in the same groups as `add`;
+/// - `xor`: XOR of the whole address with the key;
+/// - `rotl:N`: rotate the whole address left by `N` bits, then XOR the key.
+///
+/// When no op is given IPv4 uses `add` and IPv6 uses `xor`, as before.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Add,
+    Sub,
+    Xor,
+    Rotl(u32),
+}
+
+impl Op {
+    pub const DEFAULT_V4: Op = Op::Add;
+    pub const DEFAULT_V6: Op = Op::Xor;
+
+    pub fn encrypt_v4(self, from: Ipv4Addr, key: Ipv4Addr) -> Ipv4Addr {
+        match self {
+            Op::Add => encrypt_v4(from, key),
+            Op::Sub => zip_octets(from, key, u8::wrapping_sub),
+            Op::Xor => Ipv4Addr::from(from.to_bits() ^ key.to_bits()),
+            Op::Rotl(n) => Ipv4Addr::from(from.to_bits().rotate_left(n) ^ key.to_bits()),
+        }
+    }
+
+    /// Inverse of [`Op::encrypt_v4`]: the key that turns `from` into `to`.
+    pub fn derive_key_v4(self, from: Ipv4Addr, to: Ipv4Addr) -> Ipv4Addr {
+        match self {
+            Op::Add => derive_key_v4(from, to),
+            Op::Sub => zip_octets(from, to, u8::wrapping_sub),
+            Op::Xor => Ipv4Addr::from(from.to_bits() ^ to.to_bits()),
+            Op::Rotl(n) => Ipv4Addr::from(from.to_bits().rotate_left(n) ^ to.to_bits()),
+        }
+    }
+
+    pub fn encrypt_v6(self, from: Ipv6Addr, key: Ipv6Addr) -> Ipv6Addr {
+        match self {
+            Op::Add => zip_segments(from, key, u16::wrapping_add),
+            Op::Sub => zip_segments(from, key, u16::wrapping_sub),
+            Op::Xor => encrypt_v6(from, key),
+            Op::Rotl(n) => Ipv6Addr::from(from.to_bits().rotate_left(n) ^ key.to_bits()),
+        }
+    }
+
+    /// Inverse of [`Op::encrypt_v6`]: the key that turns `from` into `to`.
+    pub fn derive_key_v6(self, from: Ipv6Addr, to: Ipv6Addr) -> Ipv6Addr {
+        match self {
+            Op::Add => zip_segments(to, from, u16::wrapping_sub),
+            Op::Sub => zip_segments(from, to, u16::wrapping_sub),
+            Op::Xor => derive_key_v6(from, to),
+            Op::Rotl(n) => Ipv6Addr::from(from.to_bits().rotate_left(n) ^ to.to_bits()),
+        }
+    }
+
+    /// Collapses applying this op with each of `keys` in turn into a single
+    /// op and key. `rotl:N` becomes a rotation by `N` per hop.
+    pub fn chain_v4(self, keys: &[Ipv4Addr]) -> (Op, Ipv4Addr) {
+        let key = keys
+            .iter()
+            .fold(Ipv4Addr::UNSPECIFIED, |acc, &key| match self {
+                // Subtracting each key is subtracting their sum
+                Op::Sub => Op::Add.encrypt_v4(acc, key),
+                op => op.encrypt_v4(acc, key),
+            });
+
+        (self.repeated(keys.len(), 32), key)
+    }
+
+    /// IPv6 counterpart of [`Op::chain_v4`].
+    pub fn chain_v6(self, keys: &[Ipv6Addr]) -> (Op, Ipv6Addr) {
+        let key = keys
+            .iter()
+            .fold(Ipv6Addr::UNSPECIFIED, |acc, &key| match self {
+                Op::Sub => Op::Add.encrypt_v6(acc, key),
+                op => op.encrypt_v6(acc, key),
+            });
+
+        (self.repeated(keys.len(), 128), key)
+    }
 
-    let result: Ipv6Addr = Ipv6Addr::from(result);
+    fn repeated(self, hops: usize, bits: u32) -> Op {
+        match self {
+            Op::Rotl(n) => Op::Rotl(((n % bits) as usize * hops % bits as usize) as u32),
+            op => op,
+        }
+    }
 
-    result.to_string()
+    /// Whether every block with `host_bits` free low bits maps onto another
+    /// block. `add` and `sub` work in `chunk`-bit groups, so they carry into
+    /// the network bits when the key has host bits set in the group that the
+    /// prefix splits.
+    fn maps_blocks(self, bits: u32, chunk: u32, host_bits: u32, key: u128) -> bool {
+        match self {
+            Op::Xor => true,
+            Op::Rotl(n) => n % bits == 0 || host_bits == 0 || host_bits == bits,
+            Op::Add | Op::Sub => {
+                let partial = host_bits % chunk;
+                partial == 0 || (key >> (host_bits - partial)) & ((1 << partial) - 1) == 0
+            }
+        }
+    }
 }
-pub async fn key_2(params: Query<Day2Params>) -> impl IntoResponse {
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Op::Add => write!(f, "add"),
+            Op::Sub => write!(f, "sub"),
+            Op::Xor => write!(f, "xor"),
+            Op::Rotl(n) => write!(f, "rotl:{n}"),
+        }
+    }
+}
+
+impl FromStr for Op {
+    type Err = Day2Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "add" => Ok(Op::Add),
+            "sub" => Ok(Op::Sub),
+            "xor" => Ok(Op::Xor),
+            _ => s
+                .strip_prefix("rotl:")
+                .and_then(|n| n.parse().ok())
+                .map(Op::Rotl)
+                .ok_or_else(|| Day2Error::InvalidOp(s.to_string())),
+        }
+    }
+}
+
+fn parse_op(value: &Option<String>) -> Result<Option<Op>, Day2Error> {
+    value.as_deref().map(str::parse).transpose()
+}
+
+fn dest(from: &str, key: &str, op: Option<Op>) -> Result<String, Day2Error> {
+    let from = parse_addr("from", from)?;
+    let pair = normalize(from, "key", parse_key("key", key, from)?)?;
+
+    Ok(match pair {
+        Pair::V4 {
+            from,
+            other,
+            mapped,
+        } => render_v4(op.unwrap_or(Op::DEFAULT_V4).encrypt_v4(from, other), mapped),
+        Pair::V6 { from, other } => op
+            .unwrap_or(Op::DEFAULT_V6)
+            .encrypt_v6(from, other)
+            .to_string(),
+    })
+}
+
+/// Derives the key for one pair, along with whether an IPv4 key should be
+/// written in mapped notation.
+fn derive_key_addr(from: &str, to: &str, op: Option<Op>) -> Result<(IpAddr, bool), Day2Error> {
+    let pair = normalize(parse_addr("from", from)?, "to", parse_addr("to", to)?)?;
+
+    Ok(match pair {
+        Pair::V4 {
+            from,
+            other,
+            mapped,
+        } => {
+            let key = op.unwrap_or(Op::DEFAULT_V4).derive_key_v4(from, other);
+            (IpAddr::V4(key), mapped)
+        }
+        Pair::V6 { from, other } => {
+            let key = op.unwrap_or(Op::DEFAULT_V6).derive_key_v6(from, other);
+            (IpAddr::V6(key), false)
+        }
+    })
+}
+
+fn derive_key(from: &str, to: &str, op: Option<Op>) -> Result<String, Day2Error> {
+    let (key, mapped) = derive_key_addr(from, to, op)?;
+
+    Ok(render_ip(key, mapped))
+}
+
+fn render_ip(addr: IpAddr, mapped: bool) -> String {
+    match addr {
+        IpAddr::V4(addr) => render_v4(addr, mapped),
+        IpAddr::V6(addr) => addr.to_string(),
+    }
+}
+
+fn subnet(
+    from: &str,
+    prefix: &str,
+    key: &str,
+    op: Option<Op>,
+    paging: Paging,
+) -> Result<SubnetMapping, Day2Error> {
+    let (from, prefix) = match parse_addr("from", from)? {
+        Operand::V4 {
+            addr,
+            mapped: false,
+        } => (
+            Operand::V4 {
+                addr,
+                mapped: false,
+            },
+            parse_prefix("from", prefix, 32)?,
+        ),
+        Operand::V4 { addr, mapped: true } => match parse_prefix("from", prefix, 128)? {
+            // Only blocks inside ::ffff:0:0/96 consist of mapped addresses
+            p if p >= 96 => (Operand::V4 { addr, mapped: true }, p - 96),
+            p => (Operand::V6(addr.to_ipv6_mapped()), p),
+        },
+        Operand::V6(addr) => (Operand::V6(addr), parse_prefix("from", prefix, 128)?),
+    };
+
+    Ok(
+        match normalize(from, "key", parse_key("key", key, from)?)? {
+            Pair::V4 {
+                from,
+                other,
+                mapped,
+            } => subnet_v4(
+                from,
+                prefix,
+                other,
+                op.unwrap_or(Op::DEFAULT_V4),
+                mapped,
+                paging,
+            ),
+            Pair::V6 { from, other } => {
+                subnet_v6(from, prefix, other, op.unwrap_or(Op::DEFAULT_V6), paging)
+            }
+        },
+    )
+}
+
+fn subnet_v4(
+    from: Ipv4Addr,
+    prefix: u8,
+    key: Ipv4Addr,
+    op: Op,
+    mapped_notation: bool,
+    paging: Paging,
+) -> SubnetMapping {
+    let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+    let network = u32::from(from) & mask;
+    let host_bits = 32 - prefix as u32;
+    let contiguous = op.maps_blocks(32, 8, host_bits, key.to_bits() as u128);
+    let mapped = u32::from(op.encrypt_v4(network.into(), key)) & mask;
+
+    SubnetMapping {
+        from: render_v4_block(network.into(), prefix, mapped_notation),
+        network: contiguous.then(|| render_v4_block(mapped.into(), prefix, mapped_notation)),
+        total_hosts: host_count(host_bits),
+        page: paging.page,
+        per_page: paging.per_page,
+        hosts: paging
+            .range(host_bits)
+            .map(|i| {
+                let host = Ipv4Addr::from(network | i as u32);
+                HostMapping {
+                    from: render_v4(host, mapped_notation),
+                    to: render_v4(op.encrypt_v4(host, key), mapped_notation),
+                }
+            })
+            .collect(),
+    }
+}
+
+fn subnet_v6(from: Ipv6Addr, prefix: u8, key: Ipv6Addr, op: Op, paging: Paging) -> SubnetMapping {
+    let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+    let network = from.to_bits() & mask;
+    let host_bits = 128 - prefix as u32;
+    let contiguous = op.maps_blocks(128, 16, host_bits, key.to_bits());
+    let mapped = op.encrypt_v6(network.into(), key).to_bits() & mask;
+
+    SubnetMapping {
+        from: format!("{}/{prefix}", Ipv6Addr::from(network)),
+        network: contiguous.then(|| format!("{}/{prefix}", Ipv6Addr::from(mapped))),
+        total_hosts: host_count(host_bits),
+        page: paging.page,
+        per_page: paging.per_page,
+        hosts: paging
+            .range(host_bits)
+            .map(|i| {
+                let host = Ipv6Addr::from(network | i);
+                HostMapping {
+                    from: host.to_string(),
+                    to: op.encrypt_v6(host, key).to_string(),
+                }
+            })
+            .collect(),
+    }
+}
+
+/// `from` may be a single address or a CIDR block such as `10.0.0.0/24`, in
+/// which case the response is a JSON [`SubnetMapping`] paged by `page` and
+/// `per_page`. Either family is accepted: IPv4-mapped addresses use the IPv4
+/// cipher and an IPv4 operand paired with a plain IPv6 one is a 422. The
+/// cipher itself is picked with `op`, see [`Op`].
+pub async fn dest_2(params: Query<Day2Params>) -> Result<Response, Day2Error> {
     let params = params.0;
-    let from: Vec<u8> = params
-        .from
-        .split('.')
-        .map(|p| p.parse::<u8>().unwrap())
-        .collect();
-    let to: Vec<u8> = params
-        .to
-        .unwrap()
-        .split('.')
-        .map(|p| p.parse::<u8>().unwrap())
-        .collect();
+    let (from, prefix) = split_cidr(required("from", &params.from)?);
+    let key = required("key", &params.key)?;
+    let op = parse_op(&params.op)?;
 
-    (0..from.len())
-        .map(|i| to[i].wrapping_sub(from[i]))
-        .join(".")
+    match prefix {
+        Some(prefix) => {
+            let mapping = subnet(from, prefix, key, op, params.paging())?;
+            Ok(Json(mapping).into_response())
+        }
+        None => Ok(dest(from, key, op)?.into_response()),
+    }
 }
-pub async fn key_2_v6(params: Query<Day2Params>) -> impl IntoResponse {
+/// Same as [`dest_2`]; kept as its own route for existing IPv6 clients.
+pub async fn dest_2_v6(params: Query<Day2Params>) -> Result<Response, Day2Error> {
+    dest_2(params).await
+}
+pub async fn key_2(params: Query<Day2Params>) -> Result<String, Day2Error> {
     let params = params.0;
-    let from: Ipv6Addr = params.from.parse().unwrap();
-    let to: Ipv6Addr = params.to.unwrap().parse().unwrap();
+    derive_key(
+        required("from", &params.from)?,
+        required("to", &params.to)?,
+        parse_op(&params.op)?,
+    )
+}
+/// Same as [`key_2`]; kept as its own route for existing IPv6 clients.
+pub async fn key_2_v6(params: Query<Day2Params>) -> Result<String, Day2Error> {
+    key_2(params).await
+}
+
+/// One entry of a `/2/batch` request: `key` computes the destination and
+/// `to` derives the key, as on the single-address routes.
+#[derive(Debug, Deserialize)]
+pub struct BatchItem {
+    from: Option<String>,
+    key: Option<String>,
+    to: Option<String>,
+    op: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum BatchResult {
+    Ok { result: String },
+    Err(serde_json::Value),
+}
+
+impl From<Result<String, Day2Error>> for BatchResult {
+    fn from(result: Result<String, Day2Error>) -> Self {
+        match result {
+            Ok(result) => BatchResult::Ok { result },
+            Err(e) => BatchResult::Err(e.body()),
+        }
+    }
+}
+
+fn batch_item(item: &BatchItem) -> Result<String, Day2Error> {
+    let from = required("from", &item.from)?;
+    let op = parse_op(&item.op)?;
+
+    match (item.key.as_deref(), item.to.as_deref()) {
+        (Some(key), None) => dest(from, key, op),
+        (None, Some(to)) => derive_key(from, to, op),
+        (Some(_), Some(_)) => Err(Day2Error::UnexpectedParam("to")),
+        (None, None) => Err(Day2Error::MissingParam("key")),
+    }
+}
+
+/// Runs many dest/key computations in one request. Each item is normalized
+/// on its own, so families can be mixed across items, and failures are
+/// reported per item.
+pub async fn batch_2(Json(items): Json<Vec<BatchItem>>) -> Json<Vec<BatchResult>> {
+    Json(items.iter().map(|item| batch_item(item).into()).collect())
+}
+
+#[derive(Debug, Serialize)]
+pub struct Hop {
+    pub key: String,
+    pub to: String,
+}
+
+/// The single op and key equivalent to a whole route.
+#[derive(Debug, Serialize)]
+pub struct Composite {
+    pub op: String,
+    pub key: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Route {
+    pub from: String,
+    pub op: String,
+    pub hops: Vec<Hop>,
+    pub to: String,
+    pub composite: Composite,
+}
+
+fn route_hops<A: Copy>(
+    from: A,
+    keys: &[A],
+    encrypt: impl Fn(A, A) -> A,
+    render: impl Fn(A) -> String,
+) -> Vec<Hop> {
+    keys.iter()
+        .scan(from, |addr, &key| {
+            *addr = encrypt(*addr, key);
+            Some(Hop {
+                key: render(key),
+                to: render(*addr),
+            })
+        })
+        .collect()
+}
+
+/// Applies the cipher once per key in the comma separated `keys`, reporting
+/// every intermediate address and the composite op and key for the whole
+/// route. Keys must be in the family of `from`, as on the other routes.
+pub async fn route_2(Query(params): Query<Day2Params>) -> Result<Json<Route>, Day2Error> {
+    let from = parse_addr("from", required("from", &params.from)?)?;
+    let keys = required("keys", &params.keys)?
+        .split(',')
+        .map(|key| parse_key("keys", key.trim(), from))
+        .collect::<Result<Vec<_>, _>>()?;
+    let op = parse_op(&params.op)?;
+
+    let route = match from {
+        Operand::V4 { addr: from, mapped } => {
+            let keys = keys
+                .iter()
+                .map(|key| match key {
+                    Operand::V4 { addr, .. } => Ok(*addr),
+                    Operand::V6(_) => Err(Day2Error::MixedFamily("keys")),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let op = op.unwrap_or(Op::DEFAULT_V4);
+            let (composite_op, composite_key) = op.chain_v4(&keys);
+            let hops = route_hops(
+                from,
+                &keys,
+                |addr, key| op.encrypt_v4(addr, key),
+                |addr| render_v4(addr, mapped),
+            );
+
+            Route {
+                from: render_v4(from, mapped),
+                op: op.to_string(),
+                to: hops.last().map(|h| h.to.clone()).unwrap_or_default(),
+                hops,
+                composite: Composite {
+                    op: composite_op.to_string(),
+                    key: render_v4(composite_key, mapped),
+                },
+            }
+        }
+        Operand::V6(from) => {
+            let keys = keys
+                .iter()
+                .map(|key| match key {
+                    Operand::V6(addr) => Ok(*addr),
+                    Operand::V4 { .. } => Err(Day2Error::MixedFamily("keys")),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let op = op.unwrap_or(Op::DEFAULT_V6);
+            let (composite_op, composite_key) = op.chain_v6(&keys);
+            let hops = route_hops(
+                from,
+                &keys,
+                |addr, key| op.encrypt_v6(addr, key),
+                |addr| addr.to_string(),
+            );
+
+            Route {
+                from: from.to_string(),
+                op: op.to_string(),
+                to: hops.last().map(|h| h.to.clone()).unwrap_or_default(),
+                hops,
+                composite: Composite {
+                    op: composite_op.to_string(),
+                    key: composite_key.to_string(),
+                },
+            }
+        }
+    };
+
+    Ok(Json(route))
+}
+
+/// One captured translation passed to `/2/solve`.
+#[derive(Debug, Deserialize)]
+pub struct Observation {
+    from: Option<String>,
+    to: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ObservedPair {
+    pub index: usize,
+    pub from: String,
+    pub to: String,
+}
+
+/// A key and the observations that imply it.
+#[derive(Debug, Serialize)]
+pub struct Candidate {
+    pub key: String,
+    pub pairs: Vec<ObservedPair>,
+}
+
+fn indexed_error(index: usize, e: Day2Error) -> Response {
+    let mut body = e.body();
+    body["index"] = json!(index);
+    (e.status(), Json(body)).into_response()
+}
+
+/// Finds the single key that explains every observation. All observations
+/// must share one family (see [`dest_2`] for how IPv4-mapped addresses are
+/// treated). When they disagree the response is a 409 listing each candidate
+/// key with the pairs that imply it.
+pub async fn solve_2(
+    Query(params): Query<Day2Params>,
+    Json(observations): Json<Vec<Observation>>,
+) -> Response {
+    let op = match parse_op(&params.op) {
+        Ok(op) => op,
+        Err(e) => return e.into_response(),
+    };
+
+    let mut candidates: Vec<(IpAddr, Vec<ObservedPair>)> = Vec::new();
+    let mut by_key: HashMap<IpAddr, usize> = HashMap::new();
+    let mut mapped_notation = false;
+    for (index, observation) in observations.iter().enumerate() {
+        let (from, to) = match (
+            required("from", &observation.from),
+            required("to", &observation.to),
+        ) {
+            (Ok(from), Ok(to)) => (from, to),
+            (Err(e), _) | (_, Err(e)) => return indexed_error(index, e),
+        };
+        let (key, mapped) = match derive_key_addr(from, to, op) {
+            Ok(derived) => derived,
+            Err(e) => return indexed_error(index, e),
+        };
+        if candidates
+            .first()
+            .is_some_and(|(first, _)| first.is_ipv4() != key.is_ipv4())
+        {
+            return indexed_error(index, Day2Error::MixedFamily("from"));
+        }
+        if index == 0 {
+            mapped_notation = mapped;
+        }
+
+        let pair = ObservedPair {
+            index,
+            from: from.to_string(),
+            to: to.to_string(),
+        };
+        match by_key.get(&key) {
+            Some(&i) => candidates[i].1.push(pair),
+            None => {
+                by_key.insert(key, candidates.len());
+                candidates.push((key, vec![pair]));
+            }
+        }
+    }
+
+    let op = match candidates.first() {
+        Some((IpAddr::V4(_), _)) => op.unwrap_or(Op::DEFAULT_V4),
+        Some((IpAddr::V6(_), _)) => op.unwrap_or(Op::DEFAULT_V6),
+        None => return Day2Error::MissingParam("observations").into_response(),
+    };
+    let mut candidates = candidates
+        .into_iter()
+        .map(|(key, pairs)| Candidate {
+            key: render_ip(key, mapped_notation),
+            pairs,
+        })
+        .collect::<Vec<_>>();
+
+    if candidates.len() == 1 {
+        let Candidate { key, pairs } = candidates.remove(0);
+        let body = json!({ "key": key, "op": op.to_string(), "observations": pairs.len() });
+        (StatusCode::OK, Json(body)).into_response()
+    } else {
+        let body = json!({ "op": op.to_string(), "candidates": candidates });
+        (StatusCode::CONFLICT, Json(body)).into_response()
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PcapParams {
+    key: Option<String>,
+    v6_key: Option<String>,
+    op: Option<String>,
+}
 
-    let result = from.to_bits() ^ to.to_bits();
+/// Anonymizes a libpcap capture: every IPv4 address goes through `key` and
+/// every IPv6 address through `v6_key`, using `op` or the family default.
+pub async fn pcap_2(
+    Query(params): Query<PcapParams>,
+    capture: Bytes,
+) -> Result<Response, Day2Error> {
+    let op = parse_op(&params.op)?;
+    let keys = pcap::Keys {
+        v4: params
+            .key
+            .as_deref()
+            .map(|key| parse_v4("key", key))
+            .transpose()?
+            .map(|key| (op.unwrap_or(Op::DEFAULT_V4), key)),
+        v6: params
+            .v6_key
+            .as_deref()
+            .map(|key| parse_v6("v6_key", key))
+            .transpose()?
+            .map(|key| (op.unwrap_or(Op::DEFAULT_V6), key)),
+    };
 
-    let result: Ipv6Addr = Ipv6Addr::from(result);
+    let rewritten = pcap::rewrite(&capture, &keys)?;
 
-    result.to_string()
+    Ok(([(CONTENT_TYPE, "application/vnd.tcpdump.pcap")], rewritten).into_response())
 }
@@ -0,0 +1,483 @@
+//! Rewrites the addresses in a classic libpcap capture with the day 2 cipher.
+//!
+//! The IP header of each packet is rewritten, along with the other places
+//! addresses turn up: the protocol addresses of ARP packets, the packet an
+//! ICMP or ICMPv6 error quotes, the gateway of an ICMP redirect and the
+//! target and destination of neighbor discovery messages. A quoted packet is
+//! rewritten one level deep: errors are never sent about ICMP errors, so
+//! whatever a quoted ICMP message holds is left alone. Checksums are adjusted
+//! incrementally (RFC 1624), so truncated packets can still be fixed up and
+//! checksums that were already wrong stay wrong.
+
+use std::{
+    fmt,
+    net::{Ipv4Addr, Ipv6Addr},
+};
+
+use super::{Day2Error, Op};
+
+const GLOBAL_HEADER_LEN: usize = 24;
+const RECORD_HEADER_LEN: usize = 16;
+
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_ARP: u16 = 0x0806;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const ETHERTYPE_QINQ: u16 = 0x88a8;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+
+const PROTO_ICMP: u8 = 1;
+const PROTO_TCP: u8 = 6;
+const PROTO_UDP: u8 = 17;
+const PROTO_ICMPV6: u8 = 58;
+
+const ICMP_DEST_UNREACHABLE: u8 = 3;
+const ICMP_SOURCE_QUENCH: u8 = 4;
+const ICMP_REDIRECT: u8 = 5;
+const ICMP_TIME_EXCEEDED: u8 = 11;
+const ICMP_PARAMETER_PROBLEM: u8 = 12;
+
+const ICMPV6_NEIGHBOR_SOLICITATION: u8 = 135;
+const ICMPV6_NEIGHBOR_ADVERTISEMENT: u8 = 136;
+const ICMPV6_REDIRECT: u8 = 137;
+const NDP_OPTION_REDIRECTED_HEADER: u8 = 4;
+
+/// Keys to apply to each address family. A capture containing packets of a
+/// family without a key is rejected rather than passed through half-rewritten.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Keys {
+    pub v4: Option<(Op, Ipv4Addr)>,
+    pub v6: Option<(Op, Ipv6Addr)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PcapError {
+    Truncated,
+    PcapNg,
+    BadMagic(u32),
+    UnsupportedLinkType(u32),
+    MissingKey(&'static str),
+}
+
+impl fmt::Display for PcapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PcapError::Truncated => write!(f, "capture is truncated"),
+            PcapError::PcapNg => write!(f, "pcapng captures are not supported"),
+            PcapError::BadMagic(magic) => write!(f, "not a pcap file (magic {magic:#010x})"),
+            PcapError::UnsupportedLinkType(link) => write!(f, "unsupported link type {link}"),
+            PcapError::MissingKey(field) => write!(f, "capture needs a {field}"),
+        }
+    }
+}
+
+impl From<PcapError> for Day2Error {
+    fn from(e: PcapError) -> Self {
+        match e {
+            PcapError::MissingKey(field) => Day2Error::MissingParam(field),
+            e => Day2Error::InvalidCapture(e.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Endian {
+    Little,
+    Big,
+}
+
+impl Endian {
+    fn u32(self, bytes: &[u8]) -> u32 {
+        let bytes: [u8; 4] = bytes[..4].try_into().unwrap();
+        match self {
+            Endian::Little => u32::from_le_bytes(bytes),
+            Endian::Big => u32::from_be_bytes(bytes),
+        }
+    }
+}
+
+/// Returns a copy of `capture` with every IPv4/IPv6 source and destination
+/// address passed through the matching key.
+pub fn rewrite(capture: &[u8], keys: &Keys) -> Result<Vec<u8>, PcapError> {
+    if capture.len() < GLOBAL_HEADER_LEN {
+        return Err(PcapError::Truncated);
+    }
+    let endian = match u32::from_le_bytes(capture[..4].try_into().unwrap()) {
+        0xa1b2c3d4 | 0xa1b23c4d => Endian::Little,
+        0xd4c3b2a1 | 0x4d3cb2a1 => Endian::Big,
+        0x0a0d0d0a => return Err(PcapError::PcapNg),
+        magic => return Err(PcapError::BadMagic(magic)),
+    };
+    let link_type = endian.u32(&capture[20..24]) & 0x0fff_ffff;
+
+    let mut out = capture.to_vec();
+    let mut offset = GLOBAL_HEADER_LEN;
+    while offset < out.len() {
+        let header = out
+            .get(offset..offset + RECORD_HEADER_LEN)
+            .ok_or(PcapError::Truncated)?;
+        let captured = endian.u32(&header[8..12]) as usize;
+        let start = offset + RECORD_HEADER_LEN;
+        let packet = out
+            .get_mut(start..start + captured)
+            .ok_or(PcapError::Truncated)?;
+
+        rewrite_frame(packet, link_type, endian, keys)?;
+        offset = start + captured;
+    }
+
+    Ok(out)
+}
+
+fn rewrite_frame(
+    frame: &mut [u8],
+    link_type: u32,
+    endian: Endian,
+    keys: &Keys,
+) -> Result<(), PcapError> {
+    let (ethertype, payload) = match link_type {
+        LINKTYPE_ETHERNET => {
+            let mut offset = 12;
+            let mut ethertype = read_u16(frame, offset);
+            while matches!(ethertype, Some(ETHERTYPE_VLAN | ETHERTYPE_QINQ)) {
+                offset += 4;
+                ethertype = read_u16(frame, offset);
+            }
+            (ethertype, offset + 2)
+        }
+        LINKTYPE_LINUX_SLL => (read_u16(frame, 14), 16),
+        LINKTYPE_LINUX_SLL2 => (read_u16(frame, 0), 20),
+        LINKTYPE_NULL => {
+            // The address family is in the byte order of the capturing host
+            let family = frame.get(..4).map(|f| endian.u32(f));
+            let ethertype = match family {
+                Some(2) => Some(ETHERTYPE_IPV4),
+                Some(24 | 28 | 30) => Some(ETHERTYPE_IPV6),
+                _ => None,
+            };
+            (ethertype, 4)
+        }
+        LINKTYPE_RAW => {
+            let ethertype = match frame.first().map(|b| b >> 4) {
+                Some(4) => Some(ETHERTYPE_IPV4),
+                Some(6) => Some(ETHERTYPE_IPV6),
+                _ => None,
+            };
+            (ethertype, 0)
+        }
+        LINKTYPE_IPV4 => (Some(ETHERTYPE_IPV4), 0),
+        LINKTYPE_IPV6 => (Some(ETHERTYPE_IPV6), 0),
+        link => return Err(PcapError::UnsupportedLinkType(link)),
+    };
+
+    let Some(packet) = frame.get_mut(payload..) else {
+        return Ok(());
+    };
+    match ethertype {
+        Some(ETHERTYPE_IPV4) => rewrite_ipv4(packet, keys, None),
+        Some(ETHERTYPE_IPV6) => rewrite_ipv6(packet, keys, None),
+        Some(ETHERTYPE_ARP) => rewrite_arp(packet, keys),
+        _ => Ok(()),
+    }
+}
+
+/// The bytes a rewrite of a quoted packet replaced, and what replaced them,
+/// for fixing up the checksum of the message that quotes it. Every change
+/// starts at an even offset into that message, so the runs can be summed
+/// as if they were next to each other.
+#[derive(Debug, Default)]
+struct Changes {
+    old: Vec<u8>,
+    new: Vec<u8>,
+}
+
+impl Changes {
+    fn push(&mut self, old: &[u8], new: &[u8]) {
+        self.old.extend_from_slice(old);
+        self.new.extend_from_slice(new);
+    }
+
+    fn push_checksum(&mut self, checksum: Option<(u16, u16)>) {
+        if let Some((old, new)) = checksum {
+            self.push(&old.to_be_bytes(), &new.to_be_bytes());
+        }
+    }
+}
+
+/// Rewrites an IPv4 packet, or with `quoted` the packet an ICMP error
+/// quotes, noting what changed there.
+fn rewrite_ipv4(
+    packet: &mut [u8],
+    keys: &Keys,
+    mut quoted: Option<&mut Changes>,
+) -> Result<(), PcapError> {
+    let header_len = packet.first().map_or(0, |b| (b & 0x0f) as usize * 4);
+    if packet.len() < 20 || packet[0] >> 4 != 4 || header_len < 20 {
+        return Ok(());
+    }
+    let (op, key) = keys.v4.ok_or(PcapError::MissingKey("key"))?;
+
+    let old: [u8; 8] = packet[12..20].try_into().unwrap();
+    let src = op.encrypt_v4(Ipv4Addr::from(<[u8; 4]>::try_from(&old[..4]).unwrap()), key);
+    let dst = op.encrypt_v4(Ipv4Addr::from(<[u8; 4]>::try_from(&old[4..]).unwrap()), key);
+    packet[12..16].copy_from_slice(&src.octets());
+    packet[16..20].copy_from_slice(&dst.octets());
+    let new: [u8; 8] = packet[12..20].try_into().unwrap();
+
+    let checksum = adjust_checksum(packet, 10, &old, &new, false);
+    if let Some(changes) = quoted.as_deref_mut() {
+        changes.push(&old, &new);
+        changes.push_checksum(checksum);
+    }
+
+    // Later fragments carry no transport header
+    let fragment_offset = u16::from_be_bytes([packet[6], packet[7]]) & 0x1fff;
+    let protocol = packet[9];
+    if fragment_offset == 0 {
+        if let Some(segment) = packet.get_mut(header_len..) {
+            let checksum = adjust_transport(segment, protocol, &old, &new);
+            match quoted {
+                Some(changes) => changes.push_checksum(checksum),
+                None if protocol == PROTO_ICMP => rewrite_icmp(segment, keys)?,
+                None => {}
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Rewrites an IPv6 packet, or with `quoted` the packet an ICMPv6 message
+/// quotes, noting what changed there.
+fn rewrite_ipv6(
+    packet: &mut [u8],
+    keys: &Keys,
+    mut quoted: Option<&mut Changes>,
+) -> Result<(), PcapError> {
+    if packet.len() < 40 || packet[0] >> 4 != 6 {
+        return Ok(());
+    }
+    let (op, key) = keys.v6.ok_or(PcapError::MissingKey("v6_key"))?;
+
+    let old: [u8; 32] = packet[8..40].try_into().unwrap();
+    let src = op.encrypt_v6(
+        Ipv6Addr::from(<[u8; 16]>::try_from(&old[..16]).unwrap()),
+        key,
+    );
+    let dst = op.encrypt_v6(
+        Ipv6Addr::from(<[u8; 16]>::try_from(&old[16..]).unwrap()),
+        key,
+    );
+    packet[8..24].copy_from_slice(&src.octets());
+    packet[24..40].copy_from_slice(&dst.octets());
+    let new: [u8; 32] = packet[8..40].try_into().unwrap();
+    if let Some(changes) = quoted.as_deref_mut() {
+        changes.push(&old, &new);
+    }
+
+    // Skip extension headers to find the transport header
+    let mut next = packet[6];
+    let mut offset = 40;
+    loop {
+        match next {
+            0 | 43 | 60 => {
+                let Some(&[header, len]) = packet.get(offset..offset + 2) else {
+                    return Ok(());
+                };
+                next = header;
+                offset += (len as usize + 1) * 8;
+            }
+            44 => {
+                let Some(fragment) = packet.get(offset..offset + 4) else {
+                    return Ok(());
+                };
+                if u16::from_be_bytes([fragment[2], fragment[3]]) >> 3 != 0 {
+                    return Ok(());
+                }
+                next = fragment[0];
+                offset += 8;
+            }
+            51 => {
+                let Some(&[header, len]) = packet.get(offset..offset + 2) else {
+                    return Ok(());
+                };
+                next = header;
+                offset += (len as usize + 2) * 4;
+            }
+            _ => break,
+        }
+    }
+
+    if let Some(segment) = packet.get_mut(offset..) {
+        let checksum = adjust_transport(segment, next, &old, &new);
+        match quoted {
+            Some(changes) => changes.push_checksum(checksum),
+            None if next == PROTO_ICMPV6 => rewrite_icmpv6(segment, keys)?,
+            None => {}
+        }
+    }
+
+    Ok(())
+}
+
+fn rewrite_arp(packet: &mut [u8], keys: &Keys) -> Result<(), PcapError> {
+    // Only IPv4 over hardware addresses of any length is understood
+    let (Some(ETHERTYPE_IPV4), Some(&[hlen, 4])) = (read_u16(packet, 2), packet.get(4..6)) else {
+        return Ok(());
+    };
+    let (op, key) = keys.v4.ok_or(PcapError::MissingKey("key"))?;
+
+    let hlen = hlen as usize;
+    for start in [8 + hlen, 8 + 2 * hlen + 4] {
+        if let Some(addr) = packet.get_mut(start..start + 4) {
+            let old = Ipv4Addr::from(<[u8; 4]>::try_from(&*addr).unwrap());
+            addr.copy_from_slice(&op.encrypt_v4(old, key).octets());
+        }
+    }
+
+    Ok(())
+}
+
+/// Rewrites the addresses inside an ICMP message: the packet an error quotes
+/// and the gateway of a redirect.
+fn rewrite_icmp(message: &mut [u8], keys: &Keys) -> Result<(), PcapError> {
+    let Some(&kind) = message.first() else {
+        return Ok(());
+    };
+    let quotes = matches!(
+        kind,
+        ICMP_DEST_UNREACHABLE
+            | ICMP_SOURCE_QUENCH
+            | ICMP_REDIRECT
+            | ICMP_TIME_EXCEEDED
+            | ICMP_PARAMETER_PROBLEM
+    );
+    if !quotes || message.len() < 8 {
+        return Ok(());
+    }
+    let (op, key) = keys.v4.ok_or(PcapError::MissingKey("key"))?;
+
+    let mut changes = Changes::default();
+    if kind == ICMP_REDIRECT {
+        let gateway = Ipv4Addr::from(<[u8; 4]>::try_from(&message[4..8]).unwrap());
+        let new = op.encrypt_v4(gateway, key).octets();
+        changes.push(&gateway.octets(), &new);
+        message[4..8].copy_from_slice(&new);
+    }
+    rewrite_ipv4(&mut message[8..], keys, Some(&mut changes))?;
+
+    adjust_checksum(message, 2, &changes.old, &changes.new, false);
+    Ok(())
+}
+
+/// Rewrites the addresses inside an ICMPv6 message: the packet an error
+/// quotes, and the target, destination and redirected packet of neighbor
+/// discovery messages.
+fn rewrite_icmpv6(message: &mut [u8], keys: &Keys) -> Result<(), PcapError> {
+    let Some(&kind) = message.first() else {
+        return Ok(());
+    };
+    // (quotes a packet, addresses after the header, where options start)
+    let (quotes, addresses, options) = match kind {
+        // Errors are the types below 128
+        1..=127 => (true, 0, None),
+        ICMPV6_NEIGHBOR_SOLICITATION | ICMPV6_NEIGHBOR_ADVERTISEMENT => (false, 1, None),
+        ICMPV6_REDIRECT => (false, 2, Some(40)),
+        _ => return Ok(()),
+    };
+    if message.len() < 8 {
+        return Ok(());
+    }
+    let (op, key) = keys.v6.ok_or(PcapError::MissingKey("v6_key"))?;
+
+    let mut changes = Changes::default();
+    if quotes {
+        rewrite_ipv6(&mut message[8..], keys, Some(&mut changes))?;
+    }
+    for i in 0..addresses {
+        let start = 8 + 16 * i;
+        if let Some(addr) = message.get_mut(start..start + 16) {
+            let old = Ipv6Addr::from(<[u8; 16]>::try_from(&*addr).unwrap());
+            let new = op.encrypt_v6(old, key).octets();
+            changes.push(&old.octets(), &new);
+            addr.copy_from_slice(&new);
+        }
+    }
+    let mut offset = options.unwrap_or(message.len());
+    // Options are counted in 8 byte units, and a zero length is malformed
+    while let Some(&[option, len @ 1..=255]) = message.get(offset..offset + 2) {
+        let end = (offset + len as usize * 8).min(message.len());
+        if option == NDP_OPTION_REDIRECTED_HEADER {
+            if let Some(quoted) = message.get_mut(offset + 8..end) {
+                rewrite_ipv6(quoted, keys, Some(&mut changes))?;
+            }
+        }
+        offset = end;
+    }
+
+    adjust_checksum(message, 2, &changes.old, &changes.new, false);
+    Ok(())
+}
+
+/// Fixes the TCP, UDP or ICMPv6 checksum, all of which cover the addresses
+/// through the pseudo-header.
+fn adjust_transport(
+    segment: &mut [u8],
+    protocol: u8,
+    old: &[u8],
+    new: &[u8],
+) -> Option<(u16, u16)> {
+    match protocol {
+        PROTO_TCP => adjust_checksum(segment, 16, old, new, false),
+        PROTO_UDP => adjust_checksum(segment, 6, old, new, true),
+        PROTO_ICMPV6 => adjust_checksum(segment, 2, old, new, false),
+        _ => None,
+    }
+}
+
+/// Updates the ones' complement checksum at `at` for `old` having been
+/// replaced by `new`, and returns it before and after. For UDP a zero
+/// checksum means "none" and is left alone.
+fn adjust_checksum(
+    data: &mut [u8],
+    at: usize,
+    old: &[u8],
+    new: &[u8],
+    udp: bool,
+) -> Option<(u16, u16)> {
+    let checksum = read_u16(data, at)?;
+    if udp && checksum == 0 {
+        return None;
+    }
+    let before = checksum;
+
+    // An odd byte at the end is summed as if followed by a zero
+    let word = |b: &[u8]| u16::from_be_bytes([b[0], b.get(1).copied().unwrap_or(0)]) as u64;
+    let mut sum = !checksum as u64;
+    for (old, new) in old.chunks(2).zip(new.chunks(2)) {
+        sum += !word(old) & 0xffff;
+        sum += word(new);
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    let mut checksum = !(sum as u16);
+    if udp && checksum == 0 {
+        checksum = 0xffff;
+    }
+
+    data[at..at + 2].copy_from_slice(&checksum.to_be_bytes());
+    Some((before, checksum))
+}
+
+fn read_u16(data: &[u8], at: usize) -> Option<u16> {
+    data.get(at..at + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+}
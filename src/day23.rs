@@ -1,6 +1,3 @@
-use itertools::Itertools;
-use std::io::Read;
-
 use axum::{
     extract::Path,
     http::StatusCode,
@@ -1,27 +1,104 @@
-use anyhow::{bail, Context};
+use std::ops::Range;
+
+use anyhow::Context;
 use axum::{
-    http::StatusCode,
+    extract::{FromRequest, Multipart, Query, Request},
+    http::{
+        header::{ACCEPT, CONTENT_TYPE},
+        StatusCode,
+    },
     response::{IntoResponse, Response},
     routing::post,
-    Router,
+    Json, Router,
 };
 use cargo_manifest::Manifest;
+use deps::Graph;
+use features::Selection;
 use hyper::HeaderMap;
 use itertools::Itertools;
-use serde_json::Value as JsonValue;
-use toml::Table;
+use orders::{Catalogue, Order};
+use outdated::Index;
+use policy::Policy;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as JsonValue};
+use toml_edit::{DocumentMut, ImDocument, Item, TableLike, Value};
+use workspace::{Unresolved, Workspace};
+
+pub mod convert;
+pub mod deps;
+pub mod features;
+pub mod fmt;
+pub mod orders;
+pub mod outdated;
+pub mod policy;
+pub mod workspace;
 
 pub fn day_05_routes() -> Router {
-    Router::new().route("/manifest", post(manifest_5))
+    Router::new()
+        .route("/manifest", post(manifest_5))
+        .route("/convert", post(convert_5))
+        .route("/deps", post(deps_5))
+        .route("/features", post(features_5))
+        .route("/outdated", post(outdated_5))
+        .route("/fmt", post(fmt_5))
 }
 
-fn convert_to_toml(input: &str, format: &str) -> anyhow::Result<String> {
-    // Deserialize based on the input format
-    let value: JsonValue = match format {
-        "yaml" => serde_yaml::from_str(input).context("could not deserialize as yaml")?,
-        "json" => serde_json::from_str(input).context("could not deserialize as json")?,
-        _ => bail!("Unsupported format. Use 'yaml' or 'json'."),
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Toml,
+    Json,
+    Yaml,
+}
+
+impl Format {
+    fn from_media_type(media_type: &str) -> Option<Self> {
+        match media_type {
+            "application/json" => Some(Format::Json),
+            "application/yaml" => Some(Format::Yaml),
+            "application/toml" | "text/toml" => Some(Format::Toml),
+            _ => None,
+        }
+    }
+
+    fn media_type(self) -> &'static str {
+        match self {
+            Format::Toml => "application/toml",
+            Format::Json => "application/json",
+            Format::Yaml => "application/yaml",
+        }
+    }
+}
+
+/// TOML datetimes deserialize into a marker table; flatten them back into
+/// their string form so other formats see a plain value.
+fn plain_datetimes(value: &mut JsonValue) {
+    match value {
+        JsonValue::Object(map) => match map.get("$__toml_private_datetime") {
+            Some(datetime) if map.len() == 1 => *value = datetime.clone(),
+            _ => map.values_mut().for_each(plain_datetimes),
+        },
+        JsonValue::Array(items) => items.iter_mut().for_each(plain_datetimes),
+        _ => {}
+    }
+}
+
+fn parse_value(input: &str, format: Format) -> anyhow::Result<JsonValue> {
+    let mut value: JsonValue = match format {
+        Format::Yaml => serde_yaml::from_str(input).context("could not deserialize as yaml")?,
+        Format::Json => serde_json::from_str(input).context("could not deserialize as json")?,
+        Format::Toml => toml::from_str(input).context("could not deserialize as toml")?,
     };
+    plain_datetimes(&mut value);
+
+    Ok(value)
+}
+
+fn convert_to_toml(input: &str, format: Format) -> anyhow::Result<String> {
+    if format == Format::Toml {
+        return Ok(input.to_string());
+    }
+    // Deserialize based on the input format
+    let value = parse_value(input, format)?;
 
     // Serialize the generic `Value` to TOML
     let toml_string = toml::to_string(&value)?;
@@ -40,75 +117,727 @@ fn unsupported_type(message: &str) -> Response {
 fn no_content() -> Response {
     (StatusCode::NO_CONTENT).into_response()
 }
-async fn manifest_5(headers: HeaderMap, data: String) -> Response {
-    let data = match headers.get("Content-Type") {
-        Some(header) => match header.to_str() {
-            Ok("application/json") => convert_to_toml(&data, "json"),
-            Ok("application/yaml") => convert_to_toml(&data, "yaml"),
-            Ok("application/toml") | Ok("text/toml") => Ok(data),
-            _ => return unsupported_type("Unknown content type"),
-        },
-        _ => return bad_request("No content type header"),
-    };
-    let data = match data {
-        Ok(data) => data,
-        _ => return bad_request("could not parse data"),
+
+fn manifest_format(headers: &HeaderMap) -> Result<Format, Box<Response>> {
+    match headers.get(CONTENT_TYPE) {
+        Some(header) => header
+            .to_str()
+            .ok()
+            .and_then(Format::from_media_type)
+            .ok_or_else(|| Box::new(unsupported_type("Unknown content type"))),
+        _ => Err(Box::new(bad_request("No content type header"))),
+    }
+}
+
+/// Picks the most preferred of `offers` the client accepts, honouring `q`
+/// weights. No `Accept` header, or `*/*`, means `default`; a `type/*` range
+/// means the first offer of that type.
+fn negotiate<T: Copy>(
+    headers: &HeaderMap,
+    offers: &[(&str, T)],
+    default: T,
+) -> Result<T, Box<Response>> {
+    let Some(accept) = headers.get(ACCEPT) else {
+        return Ok(default);
     };
-    let manifest = match Manifest::from_slice(data.as_bytes()) {
-        Ok(m) => m,
-        Err(_) => return bad_request("Invalid manifest"),
+    let not_acceptable = || {
+        Box::new(
+            (
+                StatusCode::NOT_ACCEPTABLE,
+                "Unsupported accept type".to_string(),
+            )
+                .into_response(),
+        )
     };
+    let accept = accept.to_str().map_err(|_| not_acceptable())?;
 
-    // Validate the package and keywords
-    let package = match manifest.package {
-        Some(p) => p,
-        None => return bad_request("Magic keyword not provided"),
-    };
+    accept
+        .split(',')
+        .filter_map(|range| {
+            let mut parts = range.split(';').map(str::trim);
+            let media_type = parts.next()?;
+            let weight = parts
+                .find_map(|p| p.strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.parse::<f32>().ok())?;
+            let offer = match media_type.strip_suffix("/*") {
+                _ if media_type == "*/*" => Some(default),
+                Some(kind) => offers
+                    .iter()
+                    .find(|(offer, _)| offer.split('/').next() == Some(kind))
+                    .map(|(_, t)| *t),
+                None => offers
+                    .iter()
+                    .find(|(offer, _)| *offer == media_type)
+                    .map(|(_, t)| *t),
+            }?;
+            (weight > 0.0).then_some((offer, weight))
+        })
+        .enumerate()
+        .max_by(|(i, (_, a)), (j, (_, b))| a.total_cmp(b).then(j.cmp(i)))
+        .map(|(_, (offer, _))| offer)
+        .ok_or_else(not_acceptable)
+}
+
+/// The manifest format the client accepts, TOML unless it says otherwise.
+fn accepted_format(headers: &HeaderMap) -> Result<Format, Box<Response>> {
+    let offers = [
+        ("application/toml", Format::Toml),
+        ("text/toml", Format::Toml),
+        ("application/json", Format::Json),
+        ("application/yaml", Format::Yaml),
+    ];
+    negotiate(headers, &offers, Format::Toml)
+}
+
+/// Whether the client prefers a JSON report to plain text. An `Accept`
+/// header that allows neither still gets plain text, as it always has.
+fn wants_json(headers: &HeaderMap) -> bool {
+    let offers = [("text/plain", false), ("application/json", true)];
+    negotiate(headers, &offers, false).unwrap_or(false)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A single finding from manifest validation. `message` is the short text
+/// the plain-text mode responds with; `detail` explains the specific case.
+#[derive(Debug, Clone, Serialize)]
+pub struct Problem {
+    pub severity: Severity,
+    pub code: String,
+    pub path: String,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// Which manifest of a workspace upload the problem was found in.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+}
+
+impl Problem {
+    fn new(severity: Severity, code: &str, path: &str, message: &str) -> Self {
+        Self {
+            severity,
+            code: code.to_string(),
+            path: path.to_string(),
+            line: None,
+            column: None,
+            message: message.to_string(),
+            detail: None,
+            file: None,
+        }
+    }
+
+    fn error(code: &str, path: &str, message: &str) -> Self {
+        Self::new(Severity::Error, code, path, message)
+    }
 
-    let keywords = match package.keywords.and_then(|k| k.as_local()) {
-        Some(k) => k,
-        None => return bad_request("Magic keyword not provided"),
+    fn warning(code: &str, path: &str, message: &str) -> Self {
+        Self::new(Severity::Warning, code, path, message)
+    }
+
+    fn at(mut self, position: Option<(usize, usize)>) -> Self {
+        if let Some((line, column)) = position {
+            self.line = Some(line);
+            self.column = Some(column);
+        }
+        self
+    }
+
+    fn detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+}
+
+/// The uploaded manifest as TOML. Spans only mean something to the client
+/// when the upload was TOML to begin with, not converted from JSON or YAML.
+struct ManifestSource {
+    toml: String,
+    native: bool,
+}
+
+impl ManifestSource {
+    fn read(format: Format, data: &str) -> Result<Self, Report> {
+        match convert_to_toml(data, format) {
+            Ok(toml) => Ok(Self {
+                toml,
+                native: format == Format::Toml,
+            }),
+            Err(e) => {
+                let position = e
+                    .downcast_ref::<serde_json::Error>()
+                    .map(|e| (e.line(), e.column()))
+                    .or_else(|| {
+                        e.downcast_ref::<serde_yaml::Error>()
+                            .and_then(|e| e.location())
+                            .map(|l| (l.line(), l.column()))
+                    });
+                Err(Report {
+                    problems: vec![Problem::error("invalid-syntax", "", "could not parse data")
+                        .detail(format!("{e:#}"))
+                        .at(position)],
+                    ..Report::default()
+                })
+            }
+        }
+    }
+
+    fn span_of(&self, path: &[&str]) -> Option<Range<usize>> {
+        let doc = ImDocument::parse(self.toml.as_str()).ok()?;
+        let (last, parent) = path.split_last()?;
+        let parent = match parent {
+            [] => doc.as_table(),
+            parent => lookup(doc.as_table(), parent)?.as_table_like()?,
+        };
+        // Dotted keys like `version.workspace = true` only have a key span
+        let (key, item) = parent.get_key_value(last)?;
+        item.span().or_else(|| key.span())
+    }
+
+    fn position(&self, span: Option<Range<usize>>) -> Option<(usize, usize)> {
+        if !self.native {
+            return None;
+        }
+        let before = self.toml.get(..span?.start)?;
+        let line = before.matches('\n').count() + 1;
+        let column = before
+            .rsplit('\n')
+            .next()
+            .unwrap_or_default()
+            .chars()
+            .count()
+            + 1;
+        Some((line, column))
+    }
+}
+
+/// Everything learned from one manifest: all problems found, and the orders
+/// that survived validation.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub problems: Vec<Problem>,
+    pub orders: Vec<Order>,
+}
+
+impl Report {
+    fn first_error(&self) -> Option<&Problem> {
+        self.problems.iter().find(|p| p.severity == Severity::Error)
+    }
+
+    /// Folds the report for one manifest of a workspace upload into this one.
+    fn absorb(&mut self, file: &str, report: Report) {
+        self.problems
+            .extend(report.problems.into_iter().map(|problem| Problem {
+                file: Some(file.to_string()),
+                ..problem
+            }));
+        self.orders.extend(report.orders);
+    }
+
+    /// The JSON form of the report. Orders are aggregated per item; with a
+    /// `catalogue` they are also priced, with line totals, a grand total and
+    /// the items it has no price for.
+    fn into_json(self, catalogue: Option<&Catalogue>) -> Response {
+        let status = match self.first_error() {
+            Some(_) => StatusCode::BAD_REQUEST,
+            None => StatusCode::OK,
+        };
+        let lines = orders::aggregate(&self.orders, catalogue);
+        let mut body = json!({
+            "valid": status == StatusCode::OK,
+            "problems": self.problems,
+            "orders": lines,
+        });
+        if let Some(catalogue) = catalogue {
+            let total = lines.iter().filter_map(|l| l.line_total).sum::<u64>();
+            let unpriced = lines
+                .iter()
+                .filter(|l| l.unit_price.is_none())
+                .map(|l| l.item.as_str())
+                .collect::<Vec<_>>();
+            body["total"] = json!(total as f64 / 100.0);
+            body["currency"] = json!(catalogue.currency);
+            body["unpriced"] = json!(unpriced);
+        }
+
+        (status, Json(body)).into_response()
+    }
+}
+
+fn lookup<'a>(root: &'a dyn TableLike, path: &[&str]) -> Option<&'a Item> {
+    let (first, rest) = path.split_first()?;
+    let item = root.get(first)?;
+    match rest {
+        [] => Some(item),
+        rest => lookup(item.as_table_like()?, rest),
+    }
+}
+
+/// Finds the dotted key path of the deepest item whose span covers `offset`.
+fn path_at(table: &dyn TableLike, offset: usize, path: &mut Vec<String>) -> bool {
+    for (key, item) in table.iter() {
+        path.push(key.to_string());
+        let nested = match item {
+            Item::Table(t) => path_at(t, offset, path),
+            Item::Value(Value::InlineTable(t)) => path_at(t, offset, path),
+            Item::ArrayOfTables(tables) => tables.iter().enumerate().any(|(i, t)| {
+                path.push(format!("[{i}]"));
+                let found =
+                    t.span().is_some_and(|s| s.contains(&offset)) || path_at(t, offset, path);
+                if !found {
+                    path.pop();
+                }
+                found
+            }),
+            _ => false,
+        };
+        if nested || item.span().is_some_and(|s| s.contains(&offset)) {
+            return true;
+        }
+        path.pop();
+    }
+    false
+}
+
+fn key_path(root: &dyn TableLike, span: Option<&Range<usize>>) -> String {
+    let mut path = vec![];
+    if let Some(span) = span {
+        path_at(root, span.start, &mut path);
+    }
+    path.into_iter().fold(String::new(), |acc, key| {
+        match acc.is_empty() || key.starts_with('[') {
+            true => acc + &key,
+            false => acc + "." + &key,
+        }
+    })
+}
+
+fn validate(source: &ManifestSource, policy: &Policy) -> Report {
+    let mut report = Report::default();
+    let doc = match ImDocument::parse(source.toml.as_str()) {
+        Ok(doc) => doc,
+        Err(e) => {
+            report.problems.push(
+                Problem::error("invalid-toml", "", "Invalid manifest")
+                    .detail(e.message())
+                    .at(source.position(e.span())),
+            );
+            return report;
+        }
     };
+    let root = doc.as_table() as &dyn TableLike;
+
+    if let Err(e) = Manifest::from_slice(source.toml.as_bytes()) {
+        let (detail, span) = match &e {
+            cargo_manifest::Error::Parse(e) => (e.message().to_string(), e.span()),
+            e => (e.to_string(), None),
+        };
+        report.problems.push(
+            Problem::error(
+                "invalid-manifest",
+                &key_path(root, span.as_ref()),
+                "Invalid manifest",
+            )
+            .detail(detail)
+            .at(source.position(span)),
+        );
+    }
+
+    policy.check(root, source, &mut report.problems);
+    report.orders = orders::read_orders(root, source, &mut report.problems);
 
-    if !keywords.contains(&"Christmas 2024".to_string()) {
-        return bad_request("Magic keyword not provided");
+    report
+}
+
+/// One manifest out of a multipart workspace upload.
+struct Upload {
+    file: String,
+    root: bool,
+    source: Result<ManifestSource, Report>,
+}
+
+/// Reads a workspace upload: the root manifest in a part named `root`, and
+/// any number of member manifests in other parts. Each part's own
+/// `Content-Type` picks its format, defaulting to TOML.
+async fn read_uploads(mut multipart: Multipart) -> Result<Vec<Upload>, Box<Response>> {
+    let unreadable = |_| Box::new(bad_request("could not parse data"));
+    let mut uploads = vec![];
+
+    while let Some(field) = multipart.next_field().await.map_err(unreadable)? {
+        let root = field.name() == Some("root") && !uploads.iter().any(|u: &Upload| u.root);
+        let file = field
+            .file_name()
+            .or(field.name())
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("part {}", uploads.len()));
+        let format = match field.content_type() {
+            Some(media_type) => Format::from_media_type(media_type)
+                .ok_or_else(|| Box::new(unsupported_type("Unknown content type")))?,
+            None => Format::Toml,
+        };
+        let data = field.text().await.map_err(unreadable)?;
+
+        uploads.push(Upload {
+            file,
+            root,
+            source: ManifestSource::read(format, &data),
+        });
+    }
+
+    Ok(uploads)
+}
+
+fn unresolved_problem(source: &ManifestSource, unresolved: Unresolved) -> Problem {
+    let path = unresolved
+        .path
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+
+    Problem::error(
+        "unresolved-inheritance",
+        &unresolved.path.join("."),
+        "Invalid manifest",
+    )
+    .detail(unresolved.reason)
+    .at(source.position(source.span_of(&path)))
+}
+
+/// Validates every member of a workspace upload once its `workspace = true`
+/// fields have been filled in from the root. A virtual root manifest is only
+/// checked for syntax; a root with a `[package]` is validated as a member too.
+fn validate_workspace(uploads: Vec<Upload>, policy: &Policy) -> Report {
+    let mut report = Report::default();
+    let mut members = vec![];
+    let mut workspace = None;
+
+    for upload in uploads {
+        let source = match upload.source {
+            Ok(source) => source,
+            Err(failed) => {
+                report.absorb(&upload.file, failed);
+                continue;
+            }
+        };
+        let Ok(doc) = source.toml.parse::<DocumentMut>() else {
+            report.absorb(&upload.file, validate(&source, policy));
+            continue;
+        };
+        if upload.root {
+            workspace = Workspace::from_root(&doc, Some(&upload.file));
+            if workspace.is_none() {
+                report.problems.push(Problem {
+                    file: Some(upload.file.clone()),
+                    ..Problem::error("missing-workspace", "workspace", "Invalid manifest")
+                        .detail("root manifest has no [workspace] table")
+                });
+            }
+            if !doc.contains_key("package") {
+                continue;
+            }
+        }
+        members.push((upload.file, source.native, doc));
+    }
+
+    for (file, native, mut doc) in members {
+        let unresolved = workspace::resolve(
+            &mut doc,
+            workspace.as_ref(),
+            Some(&workspace::directory(&file)),
+        );
+        let source = ManifestSource {
+            toml: doc.to_string(),
+            native,
+        };
+
+        let mut member = validate(&source, policy);
+        member.problems.splice(
+            0..0,
+            unresolved
+                .into_iter()
+                .map(|u| unresolved_problem(&source, u)),
+        );
+        report.absorb(&file, member);
     }
 
-    // Parse the data into a Table
-    let data_table = match data.parse::<Table>() {
-        Ok(table) => table,
-        Err(_) => return bad_request("Failed to parse data into table"),
+    report
+}
+
+/// Validates a manifest and lists its orders, one line per item. With
+/// `Accept: application/json` the response is a [`Report`] of every problem
+/// found instead of the first error as plain text, with rejected orders, and
+/// prices if there is a price catalogue. A `multipart/form-data` upload of a
+/// workspace root and its members is validated member by member, see
+/// [`validate_workspace`].
+async fn manifest_5(request: Request) -> Response {
+    let headers = request.headers().clone();
+    let policy = match Policy::shared() {
+        Ok(policy) => policy,
+        Err(e) => {
+            tracing::error!("invalid manifest policy {e}");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Invalid policy").into_response();
+        }
     };
+    let multipart = headers
+        .get(CONTENT_TYPE)
+        .and_then(|ct| ct.to_str().ok())
+        .is_some_and(|ct| ct.starts_with("multipart/form-data"));
 
-    // Extract and process orders
-    let parsed_orders = data_table
-        .get("package")
-        .and_then(|p| p.get("metadata"))
-        .and_then(|m| m.get("orders"))
-        .and_then(|o| o.as_array())
-        .map(|orders| {
-            orders
-                .iter()
-                .filter_map(|o| {
-                    let item = o.get("item").and_then(|i| i.as_str())?;
-                    let quantity = o
-                        .get("quantity")
-                        .and_then(|q| q.as_integer())
-                        .filter(|&q| q > 0)?;
-                    Some((item.to_string(), quantity as u32))
-                })
-                .collect::<Vec<_>>()
-        })
-        .unwrap_or_default();
+    let report = if multipart {
+        let uploads = match Multipart::from_request(request, &()).await {
+            Ok(multipart) => read_uploads(multipart).await,
+            Err(rejection) => return rejection.into_response(),
+        };
+        match uploads {
+            Ok(uploads) => validate_workspace(uploads, policy),
+            Err(response) => return *response,
+        }
+    } else {
+        let format = match manifest_format(&headers) {
+            Ok(format) => format,
+            Err(response) => return *response,
+        };
+        let data = match String::from_request(request, &()).await {
+            Ok(data) => data,
+            Err(rejection) => return rejection.into_response(),
+        };
+        match ManifestSource::read(format, &data) {
+            Ok(source) => validate(&source, policy),
+            Err(report) => report,
+        }
+    };
+
+    if wants_json(&headers) {
+        return match Catalogue::shared() {
+            Ok(catalogue) => report.into_json(catalogue),
+            Err(e) => {
+                tracing::error!("invalid price catalogue {e}");
+                (StatusCode::INTERNAL_SERVER_ERROR, "Invalid price catalogue").into_response()
+            }
+        };
+    }
+    if let Some(problem) = report.first_error() {
+        return bad_request(&problem.message);
+    }
 
     // Return the appropriate response
-    if parsed_orders.is_empty() {
+    if report.orders.is_empty() {
         no_content()
     } else {
-        let result = parsed_orders
+        let result = orders::aggregate(&report.orders, None)
             .iter()
-            .map(|(k, v)| format!("{k}: {v}"))
+            .map(|line| format!("{}: {}", line.item, line.quantity))
             .join("\n");
         result.into_response()
     }
 }
+
+fn convert_manifest(data: &str, from: Format, to: Format) -> Result<String, Box<Response>> {
+    let value =
+        parse_value(data, from).map_err(|_| Box::new(bad_request("could not parse data")))?;
+    if <Manifest>::deserialize(&value).is_err() {
+        return Err(Box::new(bad_request("Invalid manifest")));
+    }
+    let unrepresentable = |e: anyhow::Error| {
+        Box::new(
+            (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("could not convert data: {e:#}"),
+            )
+                .into_response(),
+        )
+    };
+
+    let converted = match (from, to) {
+        (from, to) if from == to => Ok(data.to_string()),
+        (_, Format::Json) => serde_json::to_string_pretty(&value)
+            .map(|json| json + "\n")
+            .map_err(anyhow::Error::from),
+        (Format::Toml, Format::Yaml) => convert::toml_to_yaml(data, &value),
+        (_, Format::Yaml) => serde_yaml::to_string(&value).map_err(anyhow::Error::from),
+        (Format::Yaml, Format::Toml) => toml::to_string(&value)
+            .map_err(anyhow::Error::from)
+            .and_then(|toml| convert::yaml_comments_to_toml(data, toml)),
+        (_, Format::Toml) => toml::to_string(&value).map_err(anyhow::Error::from),
+    };
+
+    converted.map_err(unrepresentable)
+}
+
+/// Converts a manifest between TOML, JSON and YAML. The source format comes
+/// from `Content-Type` and the target from `Accept`; see [`convert`] for
+/// which comments survive the trip.
+async fn convert_5(headers: HeaderMap, data: String) -> Response {
+    let from = match manifest_format(&headers) {
+        Ok(format) => format,
+        Err(response) => return *response,
+    };
+    let to = match accepted_format(&headers) {
+        Ok(format) => format,
+        Err(response) => return *response,
+    };
+
+    match convert_manifest(&data, from, to) {
+        Ok(converted) => ([(CONTENT_TYPE, to.media_type())], converted).into_response(),
+        Err(response) => *response,
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum GraphFormat {
+    Json,
+    Dot,
+    Mermaid,
+}
+
+/// Lists the dependencies a manifest declares, as JSON, Graphviz DOT
+/// (`text/vnd.graphviz`) or a Mermaid flowchart (`text/vnd.mermaid`).
+async fn deps_5(headers: HeaderMap, data: String) -> Response {
+    let format = match manifest_format(&headers) {
+        Ok(format) => format,
+        Err(response) => return *response,
+    };
+    let offers = [
+        ("application/json", GraphFormat::Json),
+        ("text/vnd.graphviz", GraphFormat::Dot),
+        ("text/vnd.mermaid", GraphFormat::Mermaid),
+    ];
+    let output = match negotiate(&headers, &offers, GraphFormat::Json) {
+        Ok(output) => output,
+        Err(response) => return *response,
+    };
+
+    let Ok(toml) = convert_to_toml(&data, format) else {
+        return bad_request("could not parse data");
+    };
+    let Ok(manifest) = Manifest::from_slice(toml.as_bytes()) else {
+        return bad_request("Invalid manifest");
+    };
+    let graph = Graph::from_manifest(&manifest);
+
+    match output {
+        GraphFormat::Json => Json(graph).into_response(),
+        GraphFormat::Dot => ([(CONTENT_TYPE, "text/vnd.graphviz")], graph.to_dot()).into_response(),
+        GraphFormat::Mermaid => {
+            ([(CONTENT_TYPE, "text/vnd.mermaid")], graph.to_mermaid()).into_response()
+        }
+    }
+}
+
+/// Mirrors cargo's feature flags: `features` is a comma or space separated
+/// list.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct FeatureParams {
+    features: Option<String>,
+    #[serde(default)]
+    all_features: bool,
+    #[serde(default)]
+    no_default_features: bool,
+}
+
+/// Resolves a feature selection against a manifest's `[features]` table,
+/// reporting every feature it enables and the optional dependencies that
+/// come with them.
+async fn features_5(
+    headers: HeaderMap,
+    Query(params): Query<FeatureParams>,
+    data: String,
+) -> Response {
+    let format = match manifest_format(&headers) {
+        Ok(format) => format,
+        Err(response) => return *response,
+    };
+    let Ok(toml) = convert_to_toml(&data, format) else {
+        return bad_request("could not parse data");
+    };
+    let Ok(manifest) = Manifest::from_slice(toml.as_bytes()) else {
+        return bad_request("Invalid manifest");
+    };
+
+    let selection = Selection {
+        features: params
+            .features
+            .unwrap_or_default()
+            .split([',', ' '])
+            .filter(|f| !f.is_empty())
+            .map(str::to_string)
+            .collect(),
+        all_features: params.all_features,
+        no_default_features: params.no_default_features,
+    };
+
+    match features::resolve(&manifest, &selection) {
+        Ok(resolution) => Json(resolution).into_response(),
+        Err(e) => bad_request(&e.to_string()),
+    }
+}
+
+/// Checks a manifest's crates.io dependencies against the local registry
+/// index snapshot, without any network access.
+async fn outdated_5(headers: HeaderMap, data: String) -> Response {
+    let format = match manifest_format(&headers) {
+        Ok(format) => format,
+        Err(response) => return *response,
+    };
+    let Some(index) = Index::open() else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "No registry index available",
+        )
+            .into_response();
+    };
+    let Ok(toml) = convert_to_toml(&data, format) else {
+        return bad_request("could not parse data");
+    };
+    let Ok(manifest) = Manifest::from_slice(toml.as_bytes()) else {
+        return bad_request("Invalid manifest");
+    };
+
+    Json(json!({ "dependencies": outdated::outdated(&index, &manifest) })).into_response()
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct FmtParams {
+    #[serde(default)]
+    check: bool,
+}
+
+/// Formats a TOML manifest. In check mode nothing is returned for a
+/// manifest that is already canonical, and a diff otherwise.
+async fn fmt_5(headers: HeaderMap, Query(params): Query<FmtParams>, data: String) -> Response {
+    match manifest_format(&headers) {
+        Ok(Format::Toml) => {}
+        Ok(_) => return unsupported_type("Only TOML manifests can be formatted"),
+        Err(response) => return *response,
+    }
+    let Ok(mut doc) = data.parse::<DocumentMut>() else {
+        return bad_request("Invalid manifest");
+    };
+    if Manifest::from_slice(data.as_bytes()).is_err() {
+        return bad_request("Invalid manifest");
+    }
+
+    fmt::format(&mut doc);
+    let formatted = doc.to_string();
+
+    if !params.check {
+        return ([(CONTENT_TYPE, Format::Toml.media_type())], formatted).into_response();
+    }
+    match fmt::diff(&data, &formatted) {
+        None => StatusCode::OK.into_response(),
+        Some(diff) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            [(CONTENT_TYPE, "text/x-diff")],
+            diff,
+        )
+            .into_response(),
+    }
+}
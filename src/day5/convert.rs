@@ -0,0 +1,195 @@
+//! Comment-preserving conversions between TOML and YAML manifests.
+//!
+//! The data itself always goes through `serde_json::Value`; these helpers
+//! only carry comments across. TOML to YAML keeps full-line and trailing
+//! comments on keys, tables and array-of-tables entries. YAML to TOML keeps
+//! full-line comments above mapping keys; comments inside sequences, and on
+//! tables TOML never writes a header for, have nowhere to go and are dropped.
+
+use anyhow::Context;
+use serde_json::{Map, Value as JsonValue};
+use toml_edit::{DocumentMut, Item, TableLike};
+
+fn comment_lines(raw: Option<&str>, out: &mut String) {
+    for line in raw.unwrap_or_default().lines() {
+        if let Some(comment) = line.trim().strip_prefix('#') {
+            out.push('#');
+            out.push_str(comment);
+            out.push('\n');
+        }
+    }
+}
+
+fn indent(block: &str, first: &str, rest: &str) -> String {
+    block
+        .lines()
+        .enumerate()
+        .map(|(i, line)| match (i, line.is_empty()) {
+            (_, true) => "\n".to_string(),
+            (0, false) => format!("{first}{line}\n"),
+            (_, false) => format!("{rest}{line}\n"),
+        })
+        .collect()
+}
+
+fn is_block(value: &JsonValue) -> bool {
+    match value {
+        JsonValue::Object(map) => !map.is_empty(),
+        JsonValue::Array(items) => !items.is_empty(),
+        _ => false,
+    }
+}
+
+fn yaml_table(table: &dyn TableLike, data: &Map<String, JsonValue>) -> anyhow::Result<String> {
+    let mut out = String::new();
+
+    for (key, item) in table.iter() {
+        let Some(value) = data.get(key) else {
+            continue;
+        };
+        if let Some(key) = table.key(key) {
+            comment_lines(key.leaf_decor().prefix().and_then(|r| r.as_str()), &mut out);
+        }
+        if let Item::Table(t) = item {
+            comment_lines(t.decor().prefix().and_then(|r| r.as_str()), &mut out);
+        }
+        let name = serde_yaml::to_string(key)?;
+        let name = name.trim_end();
+
+        match (item, value) {
+            (Item::Table(t), JsonValue::Object(map)) if !t.is_empty() => {
+                out.push_str(&format!("{name}:\n"));
+                out.push_str(&indent(&yaml_table(t, map)?, "  ", "  "));
+            }
+            (Item::ArrayOfTables(tables), JsonValue::Array(entries)) => {
+                out.push_str(&format!("{name}:\n"));
+                for (t, entry) in tables.iter().zip(entries) {
+                    let mut comments = String::new();
+                    comment_lines(t.decor().prefix().and_then(|r| r.as_str()), &mut comments);
+                    out.push_str(&indent(&comments, "  ", "  "));
+                    let body = match entry.as_object() {
+                        Some(map) if !map.is_empty() => yaml_table(t, map)?,
+                        _ => "{}\n".to_string(),
+                    };
+                    out.push_str(&indent(&body, "  - ", "    "));
+                }
+            }
+            _ if is_block(value) => {
+                out.push_str(&format!("{name}:\n"));
+                out.push_str(&indent(&serde_yaml::to_string(value)?, "  ", "  "));
+            }
+            _ => {
+                let scalar = serde_yaml::to_string(value)?;
+                let mut trailing = String::new();
+                let suffix = item.as_value().and_then(|v| v.decor().suffix());
+                comment_lines(suffix.and_then(|r| r.as_str()), &mut trailing);
+                match trailing.trim_end() {
+                    "" => out.push_str(&format!("{name}: {}\n", scalar.trim_end())),
+                    comment => out.push_str(&format!("{name}: {} {comment}\n", scalar.trim_end())),
+                }
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+/// Renders `value`, parsed from the TOML document `toml`, as YAML with the
+/// document's comments carried over.
+pub fn toml_to_yaml(toml: &str, value: &JsonValue) -> anyhow::Result<String> {
+    let doc: DocumentMut = toml.parse().context("could not parse toml")?;
+    let data = value.as_object().context("manifest is not a table")?;
+
+    let mut out = yaml_table(doc.as_table(), data)?;
+    comment_lines(doc.trailing().as_str(), &mut out);
+
+    Ok(out)
+}
+
+/// Full-line comments in a YAML document, keyed by the path of the mapping
+/// key they sit above, plus any comments left over at the end.
+fn yaml_comments(yaml: &str) -> (Vec<(Vec<String>, String)>, String) {
+    let mut found = vec![];
+    let mut pending = String::new();
+    let mut stack: Vec<(usize, Option<String>)> = vec![];
+
+    for line in yaml.lines() {
+        let trimmed = line.trim_start();
+        let depth = line.len() - trimmed.len();
+        if trimmed.starts_with('#') {
+            pending.push_str(trimmed);
+            pending.push('\n');
+            continue;
+        }
+        if trimmed.is_empty() || trimmed.starts_with("---") {
+            continue;
+        }
+
+        stack.retain(|(d, _)| *d < depth);
+        // Sequence entries have no TOML key path to hang a comment on, and
+        // neither does anything nested under one
+        if trimmed.starts_with('-') {
+            stack.push((depth, None));
+            pending.clear();
+            continue;
+        }
+        let Some(key) = trimmed
+            .split_once(": ")
+            .map(|(k, _)| k)
+            .or_else(|| trimmed.strip_suffix(':'))
+        else {
+            pending.clear();
+            continue;
+        };
+        let key = serde_yaml::from_str::<String>(key).unwrap_or_else(|_| key.to_string());
+        stack.push((depth, Some(key)));
+
+        let path: Option<Vec<String>> = stack.iter().map(|(_, k)| k.clone()).collect();
+        match path {
+            Some(path) if !pending.is_empty() => found.push((path, std::mem::take(&mut pending))),
+            _ => pending.clear(),
+        }
+    }
+
+    (found, pending)
+}
+
+fn attach(table: &mut dyn TableLike, path: &[String], comments: &str) {
+    let Some((first, rest)) = path.split_first() else {
+        return;
+    };
+    if !rest.is_empty() {
+        if let Some(inner) = table.get_mut(first).and_then(|i| i.as_table_like_mut()) {
+            attach(inner, rest, comments);
+        }
+        return;
+    }
+
+    match table.get_mut(first) {
+        Some(Item::Table(t)) if t.is_implicit() => {}
+        Some(Item::Table(t)) => t.decor_mut().set_prefix(format!("\n{comments}")),
+        Some(_) => {
+            if let Some(mut key) = table.key_mut(first) {
+                key.leaf_decor_mut().set_prefix(comments);
+            }
+        }
+        None => {}
+    }
+}
+
+/// Copies the full-line comments of the YAML document `yaml` onto the
+/// matching keys of the generated TOML document `toml`.
+pub fn yaml_comments_to_toml(yaml: &str, toml: String) -> anyhow::Result<String> {
+    let (comments, trailing) = yaml_comments(yaml);
+    if comments.is_empty() && trailing.is_empty() {
+        return Ok(toml);
+    }
+
+    let mut doc: DocumentMut = toml.parse().context("could not parse generated toml")?;
+    for (path, comment) in comments {
+        attach(doc.as_table_mut(), &path, &comment);
+    }
+    doc.set_trailing(trailing);
+
+    Ok(doc.to_string().trim_start().to_string())
+}
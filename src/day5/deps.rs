@@ -0,0 +1,247 @@
+//! The declared dependency graph of a manifest, and its DOT and Mermaid
+//! renderings.
+
+use std::fmt::Write;
+
+use cargo_manifest::{Dependency, DepsSet, Manifest};
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Normal,
+    Dev,
+    Build,
+}
+
+impl Kind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Kind::Normal => "normal",
+            Kind::Dev => "dev",
+            Kind::Build => "build",
+        }
+    }
+}
+
+/// A package the manifest depends on, however many ways it is declared.
+#[derive(Debug, Clone, Serialize)]
+pub struct Node {
+    pub id: String,
+    pub source: String,
+}
+
+/// One dependency declaration.
+#[derive(Debug, Clone, Serialize)]
+pub struct Edge {
+    /// The name the dependency is declared under, which differs from the
+    /// node id when the dependency is renamed with `package = "..."`.
+    pub name: String,
+    pub to: String,
+    pub kind: Kind,
+    pub target: Option<String>,
+    pub requirement: Option<String>,
+    pub optional: bool,
+    pub default_features: bool,
+    pub features: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Graph {
+    pub package: String,
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+}
+
+fn source(dependency: &Dependency) -> String {
+    let detail = match dependency {
+        Dependency::Simple(_) => return "registry".to_string(),
+        Dependency::Inherited(_) => return "workspace".to_string(),
+        Dependency::Detailed(detail) => detail,
+    };
+
+    if let Some(path) = &detail.path {
+        format!("path+{path}")
+    } else if let Some(git) = &detail.git {
+        format!("git+{git}")
+    } else if let Some(registry) = &detail.registry {
+        format!("registry+{registry}")
+    } else if let Some(index) = &detail.registry_index {
+        format!("registry+{index}")
+    } else {
+        "registry".to_string()
+    }
+}
+
+fn edge(name: &str, dependency: &Dependency, kind: Kind, target: Option<&str>) -> Edge {
+    let mut edge = Edge {
+        name: name.to_string(),
+        to: name.to_string(),
+        kind,
+        target: target.map(str::to_string),
+        requirement: None,
+        optional: false,
+        default_features: true,
+        features: vec![],
+    };
+
+    match dependency {
+        Dependency::Simple(version) => edge.requirement = Some(version.clone()),
+        Dependency::Inherited(inherited) => {
+            edge.optional = inherited.optional.unwrap_or(false);
+            edge.features = inherited.features.clone().unwrap_or_default();
+        }
+        Dependency::Detailed(detail) => {
+            edge.to = detail.package.clone().unwrap_or(edge.to);
+            edge.requirement = detail.version.clone();
+            edge.optional = detail.optional.unwrap_or(false);
+            edge.default_features = detail.default_features.unwrap_or(true);
+            edge.features = detail.features.clone().unwrap_or_default();
+        }
+    }
+
+    edge
+}
+
+impl Graph {
+    pub fn from_manifest(manifest: &Manifest) -> Self {
+        let mut graph = Graph {
+            package: manifest
+                .package
+                .as_ref()
+                .map_or_else(|| "workspace".to_string(), |p| p.name.clone()),
+            nodes: vec![],
+            edges: vec![],
+        };
+
+        let sets = [
+            (&manifest.dependencies, Kind::Normal),
+            (&manifest.dev_dependencies, Kind::Dev),
+            (&manifest.build_dependencies, Kind::Build),
+        ];
+        for (deps, kind) in sets {
+            graph.add(deps.iter().flatten(), kind, None);
+        }
+        for (target, deps) in manifest.target.iter().flatten() {
+            let sets: [(&DepsSet, Kind); 3] = [
+                (&deps.dependencies, Kind::Normal),
+                (&deps.dev_dependencies, Kind::Dev),
+                (&deps.build_dependencies, Kind::Build),
+            ];
+            for (deps, kind) in sets {
+                graph.add(deps.iter(), kind, Some(target));
+            }
+        }
+
+        graph
+    }
+
+    fn add<'a>(
+        &mut self,
+        deps: impl Iterator<Item = (&'a String, &'a Dependency)>,
+        kind: Kind,
+        target: Option<&str>,
+    ) {
+        for (name, dependency) in deps {
+            let edge = edge(name, dependency, kind, target);
+            if !self.nodes.iter().any(|n| n.id == edge.to) {
+                self.nodes.push(Node {
+                    id: edge.to.clone(),
+                    source: source(dependency),
+                });
+            }
+            self.edges.push(edge);
+        }
+    }
+
+    fn label(edge: &Edge) -> String {
+        let mut parts = vec![edge.kind.as_str().to_string()];
+        if edge.name != edge.to {
+            parts.push(format!("as {}", edge.name));
+        }
+        if let Some(requirement) = &edge.requirement {
+            parts.push(requirement.clone());
+        }
+        if let Some(target) = &edge.target {
+            parts.push(target.clone());
+        }
+        if edge.optional {
+            parts.push("optional".to_string());
+        }
+        if !edge.default_features {
+            parts.push("no default features".to_string());
+        }
+        if !edge.features.is_empty() {
+            parts.push(format!("features: {}", edge.features.join(" ")));
+        }
+        parts.join(", ")
+    }
+
+    /// Renders the graph as Graphviz DOT. Optional dependencies are dashed,
+    /// and dev and build dependencies get their own colour.
+    pub fn to_dot(&self) -> String {
+        let quote = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+        let mut out = String::new();
+
+        writeln!(out, "digraph {} {{", quote(&self.package)).unwrap();
+        writeln!(out, "    {} [shape=box];", quote(&self.package)).unwrap();
+        for node in &self.nodes {
+            writeln!(
+                out,
+                "    {} [tooltip={}];",
+                quote(&node.id),
+                quote(&node.source)
+            )
+            .unwrap();
+        }
+        for edge in &self.edges {
+            let color = match edge.kind {
+                Kind::Normal => "black",
+                Kind::Dev => "blue",
+                Kind::Build => "darkgreen",
+            };
+            let style = if edge.optional { "dashed" } else { "solid" };
+            writeln!(
+                out,
+                "    {} -> {} [label={}, color={color}, style={style}];",
+                quote(&self.package),
+                quote(&edge.to),
+                quote(&Self::label(edge)),
+            )
+            .unwrap();
+        }
+        out.push_str("}\n");
+
+        out
+    }
+
+    /// Renders the graph as a Mermaid flowchart. Optional dependencies use
+    /// dotted arrows.
+    pub fn to_mermaid(&self) -> String {
+        let escape = |s: &str| s.replace('"', "#quot;").replace('|', "#124;");
+        let id = |to: &str| {
+            self.nodes
+                .iter()
+                .position(|n| n.id == to)
+                .map_or_else(|| "root".to_string(), |i| format!("n{i}"))
+        };
+        let mut out = String::from("graph LR\n");
+
+        writeln!(out, "    root[\"{}\"]", escape(&self.package)).unwrap();
+        for (i, node) in self.nodes.iter().enumerate() {
+            writeln!(out, "    n{i}[\"{}\"]", escape(&node.id)).unwrap();
+        }
+        for edge in &self.edges {
+            let arrow = if edge.optional { "-.->" } else { "-->" };
+            writeln!(
+                out,
+                "    root {arrow}|\"{}\"| {}",
+                escape(&Self::label(edge)),
+                id(&edge.to),
+            )
+            .unwrap();
+        }
+
+        out
+    }
+}
@@ -0,0 +1,250 @@
+//! Feature unification within a single package: which features a selection
+//! turns on, which optional dependencies that activates, and why.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    fmt,
+};
+
+use cargo_manifest::Manifest;
+use serde::Serialize;
+
+use super::deps::{Graph, Kind};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FeatureError {
+    UnknownFeature {
+        feature: String,
+        from: Option<String>,
+    },
+    UnknownDependency {
+        dependency: String,
+        from: Option<String>,
+    },
+}
+
+impl fmt::Display for FeatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (what, name, from) = match self {
+            FeatureError::UnknownFeature { feature, from } => ("feature", feature, from),
+            FeatureError::UnknownDependency { dependency, from } => {
+                ("optional dependency", dependency, from)
+            }
+        };
+        match from {
+            Some(from) => write!(f, "Unknown {what} `{name}` in feature `{from}`"),
+            None => write!(f, "Unknown {what} `{name}`"),
+        }
+    }
+}
+
+/// An optional dependency turned on by the selection. `because` is the
+/// chain of features from the one requested down to the one that names the
+/// dependency.
+#[derive(Debug, Clone, Serialize)]
+pub struct Activation {
+    pub name: String,
+    pub because: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Resolution {
+    pub requested: Vec<String>,
+    pub features: BTreeSet<String>,
+    pub optional_dependencies: Vec<Activation>,
+    /// Features enabled on dependencies through `pkg/feat` and `pkg?/feat`.
+    pub dependency_features: BTreeMap<String, BTreeSet<String>>,
+}
+
+/// The feature selection, as cargo's `--features`, `--all-features` and
+/// `--no-default-features` flags would give it.
+#[derive(Debug, Clone, Default)]
+pub struct Selection {
+    pub features: Vec<String>,
+    pub all_features: bool,
+    pub no_default_features: bool,
+}
+
+struct Resolver {
+    table: BTreeMap<String, Vec<String>>,
+    dependencies: BTreeSet<String>,
+    optional: BTreeSet<String>,
+    /// Each enabled feature, with the feature that enabled it.
+    enabled: BTreeMap<String, Option<String>>,
+    /// Each activated optional dependency, with the feature that named it.
+    active: BTreeMap<String, Option<String>>,
+    weak: Vec<(String, String)>,
+    dependency_features: BTreeMap<String, BTreeSet<String>>,
+    queue: VecDeque<(String, Option<String>)>,
+}
+
+impl Resolver {
+    fn new(manifest: &Manifest) -> Self {
+        let graph = Graph::from_manifest(manifest);
+        let dependencies = graph
+            .edges
+            .iter()
+            .map(|e| e.name.clone())
+            .collect::<BTreeSet<_>>();
+        let optional = graph
+            .edges
+            .iter()
+            .filter(|e| e.optional && e.kind != Kind::Dev)
+            .map(|e| e.name.clone())
+            .collect::<BTreeSet<_>>();
+
+        let mut table = manifest
+            .features
+            .clone()
+            .unwrap_or_default()
+            .into_iter()
+            .collect::<BTreeMap<_, _>>();
+        // An optional dependency gets a feature of its own name unless some
+        // feature refers to it with `dep:`
+        let explicit = table
+            .values()
+            .flatten()
+            .filter_map(|v| v.strip_prefix("dep:"))
+            .map(str::to_string)
+            .collect::<BTreeSet<_>>();
+        for dep in optional.difference(&explicit) {
+            table
+                .entry(dep.clone())
+                .or_insert_with(|| vec![format!("dep:{dep}")]);
+        }
+
+        Self {
+            table,
+            dependencies,
+            optional,
+            enabled: BTreeMap::new(),
+            active: BTreeMap::new(),
+            weak: vec![],
+            dependency_features: BTreeMap::new(),
+            queue: VecDeque::new(),
+        }
+    }
+
+    fn activate(&mut self, dep: &str, from: &Option<String>) -> Result<(), FeatureError> {
+        if !self.optional.contains(dep) {
+            return Err(FeatureError::UnknownDependency {
+                dependency: dep.to_string(),
+                from: from.clone(),
+            });
+        }
+        self.active
+            .entry(dep.to_string())
+            .or_insert_with(|| from.clone());
+        Ok(())
+    }
+
+    fn known_dependency(&self, dep: &str, from: &Option<String>) -> Result<(), FeatureError> {
+        match self.dependencies.contains(dep) {
+            true => Ok(()),
+            false => Err(FeatureError::UnknownDependency {
+                dependency: dep.to_string(),
+                from: from.clone(),
+            }),
+        }
+    }
+
+    fn step(&mut self, value: String, from: Option<String>) -> Result<(), FeatureError> {
+        if let Some(dep) = value.strip_prefix("dep:") {
+            return self.activate(dep, &from);
+        }
+        if let Some((dep, feature)) = value.split_once('/') {
+            if let Some(dep) = dep.strip_suffix('?') {
+                self.known_dependency(dep, &from)?;
+                self.weak.push((dep.to_string(), feature.to_string()));
+                return Ok(());
+            }
+            self.known_dependency(dep, &from)?;
+            self.dependency_features
+                .entry(dep.to_string())
+                .or_default()
+                .insert(feature.to_string());
+            if self.optional.contains(dep) {
+                self.activate(dep, &from)?;
+                if self.table.contains_key(dep) {
+                    self.queue.push_back((dep.to_string(), from));
+                }
+            }
+            return Ok(());
+        }
+
+        let Some(values) = self.table.get(&value) else {
+            return Err(FeatureError::UnknownFeature {
+                feature: value,
+                from,
+            });
+        };
+        if self.enabled.contains_key(&value) {
+            return Ok(());
+        }
+        let parent = Some(value.clone());
+        self.queue
+            .extend(values.iter().map(|v| (v.clone(), parent.clone())));
+        self.enabled.insert(value, from);
+        Ok(())
+    }
+
+    fn because(&self, dep: &str) -> Vec<String> {
+        let mut chain = vec![];
+        let mut feature = self.active.get(dep).cloned().flatten();
+        while let Some(f) = feature {
+            feature = self.enabled.get(&f).cloned().flatten();
+            chain.push(f);
+        }
+        chain.reverse();
+        chain
+    }
+}
+
+/// Works out everything `selection` turns on in `manifest`.
+pub fn resolve(manifest: &Manifest, selection: &Selection) -> Result<Resolution, FeatureError> {
+    let mut resolver = Resolver::new(manifest);
+
+    let mut requested = match selection.all_features {
+        true => resolver.table.keys().cloned().collect(),
+        false => selection.features.clone(),
+    };
+    if !selection.no_default_features
+        && resolver.table.contains_key("default")
+        && !requested.iter().any(|f| f == "default")
+    {
+        requested.insert(0, "default".to_string());
+    }
+
+    resolver
+        .queue
+        .extend(requested.iter().map(|f| (f.clone(), None)));
+    while let Some((value, from)) = resolver.queue.pop_front() {
+        resolver.step(value, from)?;
+    }
+    // `pkg?/feat` only applies once something else has turned `pkg` on
+    for (dep, feature) in std::mem::take(&mut resolver.weak) {
+        if resolver.active.contains_key(&dep) || !resolver.optional.contains(&dep) {
+            resolver
+                .dependency_features
+                .entry(dep)
+                .or_default()
+                .insert(feature);
+        }
+    }
+
+    let optional_dependencies = resolver
+        .active
+        .keys()
+        .map(|dep| Activation {
+            name: dep.clone(),
+            because: resolver.because(dep),
+        })
+        .collect();
+
+    Ok(Resolution {
+        requested,
+        features: resolver.enabled.keys().cloned().collect(),
+        optional_dependencies,
+        dependency_features: resolver.dependency_features,
+    })
+}
@@ -0,0 +1,157 @@
+//! Canonical formatting for manifests: `[package]` keys in the order the
+//! Cargo style guide gives, dependency tables sorted by name, and every
+//! dependency written on one line, as a bare version requirement when
+//! that is all it has and as an inline table otherwise. Comments move along
+//! with the key they sit on.
+
+use similar::TextDiff;
+use toml_edit::{Decor, DocumentMut, InlineTable, Item, Key, Table, Value};
+
+use super::workspace::DEPENDENCY_TABLES;
+
+/// The comment lines of `decor`, dropping the blank lines and indentation
+/// around them.
+fn comments(decor: &Decor) -> String {
+    [decor.prefix(), decor.suffix()]
+        .into_iter()
+        .flatten()
+        .filter_map(|raw| raw.as_str())
+        .flat_map(str::lines)
+        .map(str::trim)
+        .filter(|line| line.starts_with('#'))
+        .map(|line| format!("{line}\n"))
+        .collect()
+}
+
+/// Every comment inside a table that is about to be written inline.
+fn table_comments(table: &Table) -> String {
+    let mut out = comments(table.decor());
+    for (key, item) in table.iter() {
+        if let Some(key) = table.key(key) {
+            out.push_str(&comments(key.leaf_decor()));
+        }
+        match item {
+            Item::Table(table) => out.push_str(&table_comments(table)),
+            Item::Value(value) => out.push_str(&comments(value.decor())),
+            _ => {}
+        }
+    }
+    out
+}
+
+/// `{ version = "1" }` is just `"1"`.
+fn simplify(mut table: InlineTable) -> Value {
+    if table.len() == 1 {
+        if let Some(version) = table.get("version").and_then(Value::as_str) {
+            return Value::from(version);
+        }
+    }
+    table.fmt();
+    Value::InlineTable(table)
+}
+
+fn format_dependencies(deps: &mut Table) {
+    for (mut key, item) in deps.iter_mut() {
+        let mut prefix = comments(key.leaf_decor());
+        let (mut value, suffix) = match std::mem::take(item) {
+            Item::Table(table) => {
+                prefix.push_str(&table_comments(&table));
+                (simplify(table.into_inline_table()), None)
+            }
+            Item::Value(Value::InlineTable(table)) => {
+                let suffix = table.decor().suffix().cloned();
+                (simplify(table), suffix)
+            }
+            Item::Value(value) => {
+                let suffix = value.decor().suffix().cloned();
+                (value, suffix)
+            }
+            other => {
+                *item = other;
+                continue;
+            }
+        };
+
+        key.leaf_decor_mut().set_prefix(prefix);
+        key.leaf_decor_mut().set_suffix(" ");
+        value.decor_mut().set_prefix(" ");
+        let comment = suffix
+            .as_ref()
+            .and_then(|s| s.as_str())
+            .map_or("", str::trim);
+        match comment.is_empty() {
+            true => value.decor_mut().set_suffix(""),
+            false => value.decor_mut().set_suffix(format!(" {comment}")),
+        }
+        *item = Item::Value(value);
+    }
+    deps.set_implicit(false);
+    deps.sort_values();
+}
+
+/// Name and version first, description last, everything else in between
+/// in alphabetical order.
+fn package_rank(key: &Key) -> (u8, &str) {
+    match key.get() {
+        "name" => (0, ""),
+        "version" => (1, ""),
+        "description" => (3, ""),
+        other => (2, other),
+    }
+}
+
+fn format_package(package: &mut Table) {
+    for (mut key, item) in package.iter_mut() {
+        if item.is_value() {
+            let prefix = comments(key.leaf_decor());
+            key.leaf_decor_mut().set_prefix(prefix);
+        }
+    }
+    package.sort_values_by(|a, _, b, _| package_rank(a).cmp(&package_rank(b)));
+}
+
+/// The dependency tables of `table`, one of the manifest root or a
+/// `[target.'cfg(...)']` table.
+fn format_dependency_tables(table: &mut Table) {
+    for name in DEPENDENCY_TABLES {
+        if let Some(deps) = table.get_mut(name).and_then(Item::as_table_mut) {
+            format_dependencies(deps);
+        }
+    }
+}
+
+/// Rewrites `doc` in canonical form.
+pub fn format(doc: &mut DocumentMut) {
+    let root = doc.as_table_mut();
+
+    if let Some(package) = root.get_mut("package").and_then(Item::as_table_mut) {
+        format_package(package);
+    }
+    format_dependency_tables(root);
+    if let Some(targets) = root.get_mut("target").and_then(Item::as_table_mut) {
+        for (_, target) in targets.iter_mut() {
+            if let Some(target) = target.as_table_mut() {
+                format_dependency_tables(target);
+            }
+        }
+    }
+    if let Some(deps) = root
+        .get_mut("workspace")
+        .and_then(Item::as_table_mut)
+        .and_then(|w| w.get_mut("dependencies"))
+        .and_then(Item::as_table_mut)
+    {
+        format_dependencies(deps);
+    }
+}
+
+/// A unified diff from `original` to `formatted`, or `None` if they are the
+/// same.
+pub fn diff(original: &str, formatted: &str) -> Option<String> {
+    (original != formatted).then(|| {
+        TextDiff::from_lines(original, formatted)
+            .unified_diff()
+            .header("Cargo.toml", "Cargo.toml")
+            .to_string()
+    })
+}
@@ -0,0 +1,199 @@
+//! The `package.metadata.orders` schema, and pricing orders against the
+//! catalogue in `assets/day5_prices.toml` (or the file named by
+//! `DAY5_PRICES`). Without a catalogue file orders aren't priced.
+
+use std::{
+    collections::HashMap, env, fs::read_to_string, io::ErrorKind, num::NonZeroU32, ops::Range,
+    sync::OnceLock,
+};
+
+use serde::{de::IntoDeserializer, Deserialize, Serialize};
+use toml_edit::{Item, TableLike, Value};
+
+use super::{lookup, ManifestSource, Problem};
+
+const PATH: &str = "package.metadata.orders";
+const CATALOGUE: &str = "assets/day5_prices.toml";
+
+/// One entry of `package.metadata.orders`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Order {
+    pub item: String,
+    pub quantity: NonZeroU32,
+}
+
+/// Unit prices by item name. Prices are kept in cents so totals add up
+/// exactly.
+#[derive(Debug, Clone, Default)]
+pub struct Catalogue {
+    pub currency: Option<String>,
+    prices: HashMap<String, u64>,
+}
+
+#[derive(Deserialize)]
+struct CatalogueFile {
+    currency: Option<String>,
+    prices: HashMap<String, f64>,
+}
+
+impl Catalogue {
+    /// Reads the catalogue file. A missing file means there is no
+    /// catalogue; a broken one is an error, rather than every order
+    /// quietly going unpriced.
+    pub fn load() -> Result<Option<Self>, String> {
+        let path = env::var("DAY5_PRICES").unwrap_or_else(|_| CATALOGUE.to_string());
+        match read_to_string(&path) {
+            Ok(data) => Self::parse(&data)
+                .map(Some)
+                .map_err(|e| format!("{path}: {}", e.message())),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("{path}: {e}")),
+        }
+    }
+
+    /// The catalogue orders are priced against, read on first use.
+    pub fn shared() -> Result<Option<&'static Self>, &'static str> {
+        static SHARED: OnceLock<Result<Option<Catalogue>, String>> = OnceLock::new();
+        match SHARED.get_or_init(Self::load) {
+            Ok(catalogue) => Ok(catalogue.as_ref()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Reads a catalogue file. Prices that aren't a finite, non-negative
+    /// amount are left out, so those items count as unpriced.
+    pub fn parse(data: &str) -> Result<Self, toml::de::Error> {
+        let file: CatalogueFile = toml::from_str(data)?;
+        let prices = file
+            .prices
+            .into_iter()
+            .filter(|(_, price)| price.is_finite() && *price >= 0.0)
+            .map(|(item, price)| (item, (price * 100.0).round() as u64))
+            .collect();
+
+        Ok(Self {
+            currency: file.currency,
+            prices,
+        })
+    }
+
+    pub fn price(&self, item: &str) -> Option<u64> {
+        self.prices.get(item).copied()
+    }
+}
+
+/// All orders for one item, summed up and priced if the catalogue knows it.
+#[derive(Debug, Clone, Serialize)]
+pub struct OrderLine {
+    pub item: String,
+    pub quantity: u64,
+    #[serde(skip_serializing_if = "Option::is_none", serialize_with = "money")]
+    pub unit_price: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none", serialize_with = "money")]
+    pub line_total: Option<u64>,
+}
+
+/// Writes an amount in cents as a decimal number.
+fn money<S: serde::Serializer>(cents: &Option<u64>, serializer: S) -> Result<S::Ok, S::Error> {
+    match cents {
+        Some(cents) => serializer.serialize_f64(*cents as f64 / 100.0),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Sums the quantities of orders for the same item, keeping items in the
+/// order they first appeared.
+pub fn aggregate(orders: &[Order], catalogue: Option<&Catalogue>) -> Vec<OrderLine> {
+    let mut lines: Vec<OrderLine> = vec![];
+    let mut index: HashMap<String, usize> = HashMap::new();
+
+    for order in orders {
+        let quantity = u64::from(order.quantity.get());
+        match index.get(&order.item) {
+            Some(&i) => lines[i].quantity += quantity,
+            None => {
+                index.insert(order.item.clone(), lines.len());
+                lines.push(OrderLine {
+                    item: order.item.clone(),
+                    quantity,
+                    unit_price: catalogue.and_then(|c| c.price(&order.item)),
+                    line_total: None,
+                });
+            }
+        }
+    }
+    for line in &mut lines {
+        line.line_total = line.unit_price.map(|price| price * line.quantity);
+    }
+
+    lines
+}
+
+/// An order entry as a value to deserialize, the table it came from if it
+/// is one, and where it sits in the source.
+struct OrderEntry<'a> {
+    value: Value,
+    table: Option<&'a dyn TableLike>,
+    span: Option<Range<usize>>,
+}
+
+/// Reads `package.metadata.orders` from a manifest. Entries that don't fit
+/// the [`Order`] schema are left out and reported as warnings.
+pub(super) fn read_orders(
+    root: &dyn TableLike,
+    source: &ManifestSource,
+    problems: &mut Vec<Problem>,
+) -> Vec<Order> {
+    let entries: Vec<OrderEntry> = match lookup(root, &["package", "metadata", "orders"]) {
+        None => return vec![],
+        Some(Item::ArrayOfTables(tables)) => tables
+            .iter()
+            .map(|t| OrderEntry {
+                value: Value::InlineTable(t.clone().into_inline_table()),
+                table: Some(t),
+                span: t.span(),
+            })
+            .collect(),
+        Some(Item::Value(Value::Array(array))) => array
+            .iter()
+            .map(|v| OrderEntry {
+                value: v.clone(),
+                table: v.as_inline_table().map(|t| t as &dyn TableLike),
+                span: v.span(),
+            })
+            .collect(),
+        Some(other) => {
+            problems.push(
+                Problem::warning("invalid-orders", PATH, "Invalid orders")
+                    .detail("orders must be an array of tables")
+                    .at(source.position(other.span())),
+            );
+            return vec![];
+        }
+    };
+
+    entries
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, entry)| {
+            let deserializer = entry.value.into_deserializer();
+            let e = match serde_path_to_error::deserialize::<_, Order>(deserializer) {
+                Ok(order) => return Some(order),
+                Err(e) => e,
+            };
+
+            let field = e.path().to_string();
+            let (path, span) = match entry.table.and_then(|t| t.get(&field)) {
+                Some(value) => (format!("{PATH}[{i}].{field}"), value.span()),
+                None => (format!("{PATH}[{i}]"), None),
+            };
+            problems.push(
+                Problem::warning("invalid-order", &path, "Invalid order")
+                    .detail(e.into_inner().message())
+                    .at(source.position(span.or(entry.span))),
+            );
+
+            None
+        })
+        .collect()
+}
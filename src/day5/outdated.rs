@@ -0,0 +1,196 @@
+//! Checks dependency requirements against a registry index snapshot on
+//! disk, laid out like the crates.io sparse index: one file per crate, one
+//! JSON object per published version.
+
+use std::{
+    env,
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
+
+use cargo_manifest::Manifest;
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+
+use super::deps::{Graph, Kind};
+
+const INDEX: &str = "assets/index";
+
+/// The fields of an index entry this check needs.
+#[derive(Debug, Clone, Deserialize)]
+struct IndexEntry {
+    vers: String,
+    #[serde(default)]
+    yanked: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Published {
+    version: Version,
+    yanked: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Status {
+    /// A published version matches the requirement.
+    Ok,
+    /// The crate isn't in the index at all.
+    NotFound,
+    /// The requirement isn't a valid semver requirement.
+    InvalidRequirement,
+    /// No published version matches the requirement.
+    NoMatch,
+    /// Only yanked versions match the requirement.
+    YankedOnly,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub name: String,
+    pub package: String,
+    pub kind: Kind,
+    pub target: Option<String>,
+    pub requirement: String,
+    pub status: Status,
+    /// The newest version the requirement resolves to.
+    pub resolved: Option<String>,
+    /// A newer version that is semver compatible with `resolved` but not
+    /// allowed by the requirement, e.g. past an `=` or `~` pin.
+    pub compatible_upgrade: Option<String>,
+    /// The newest version overall, when it breaks compatibility.
+    pub incompatible_upgrade: Option<String>,
+}
+
+/// A registry index snapshot, `assets/index` unless `DAY5_INDEX` says
+/// otherwise.
+#[derive(Debug, Clone)]
+pub struct Index {
+    root: PathBuf,
+}
+
+impl Index {
+    pub fn open() -> Option<Self> {
+        let root = env::var("DAY5_INDEX").map_or_else(|_| PathBuf::from(INDEX), PathBuf::from);
+        root.is_dir().then_some(Self { root })
+    }
+
+    /// Where the sparse index keeps a crate's file, if `name` is a valid
+    /// crate name. Anything else could climb out of the index.
+    fn path(&self, name: &str) -> Option<PathBuf> {
+        if !valid_name(name) {
+            return None;
+        }
+        let name = name.to_ascii_lowercase();
+        let prefix = match name.len() {
+            1 => PathBuf::from("1"),
+            2 => PathBuf::from("2"),
+            3 => Path::new("3").join(&name[..1]),
+            _ => Path::new(&name[..2]).join(&name[2..4]),
+        };
+        Some(self.root.join(prefix).join(name))
+    }
+
+    fn versions(&self, name: &str) -> Option<Vec<Published>> {
+        let data = read_to_string(self.path(name)?).ok()?;
+
+        let versions = data
+            .lines()
+            .filter_map(|line| serde_json::from_str::<IndexEntry>(line).ok())
+            .filter_map(|entry| {
+                Some(Published {
+                    version: Version::parse(&entry.vers).ok()?,
+                    yanked: entry.yanked,
+                })
+            })
+            .collect();
+        Some(versions)
+    }
+}
+
+/// ASCII letters, digits, `-` and `_`, at most 64 characters, as crates.io
+/// allows.
+fn valid_name(name: &str) -> bool {
+    (1..=64).contains(&name.len())
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// Cargo's notion of compatibility: the same leftmost non-zero component.
+fn compatible(a: &Version, b: &Version) -> bool {
+    match (a.major, a.minor) {
+        (0, 0) => b.major == 0 && b.minor == 0 && a.patch == b.patch,
+        (0, minor) => b.major == 0 && b.minor == minor,
+        (major, _) => b.major == major,
+    }
+}
+
+fn check(index: &Index, package: &str, requirement: &str) -> (Status, [Option<Version>; 3]) {
+    let Ok(req) = VersionReq::parse(requirement) else {
+        return (Status::InvalidRequirement, Default::default());
+    };
+    let Some(published) = index.versions(package) else {
+        return (Status::NotFound, Default::default());
+    };
+
+    let live = || published.iter().filter(|p| !p.yanked).map(|p| &p.version);
+    let Some(resolved) = live().filter(|v| req.matches(v)).max().cloned() else {
+        let status = match published.iter().any(|p| req.matches(&p.version)) {
+            true => Status::YankedOnly,
+            false => Status::NoMatch,
+        };
+        return (status, Default::default());
+    };
+
+    // Pre-releases only count as upgrades for something already on one
+    let candidates = || live().filter(|v| v.pre.is_empty() || !resolved.pre.is_empty());
+    let compatible_upgrade = candidates()
+        .filter(|v| compatible(&resolved, v) && **v > resolved)
+        .max()
+        .cloned();
+    let incompatible_upgrade = candidates()
+        .max()
+        .filter(|v| !compatible(&resolved, v) && **v > resolved)
+        .cloned();
+
+    (
+        Status::Ok,
+        [Some(resolved), compatible_upgrade, incompatible_upgrade],
+    )
+}
+
+/// Checks every crates.io dependency of `manifest` that states a version
+/// requirement. Path, git, alternative registry and workspace-inherited
+/// dependencies are left out.
+pub fn outdated(index: &Index, manifest: &Manifest) -> Vec<Check> {
+    let graph = Graph::from_manifest(manifest);
+
+    graph
+        .edges
+        .iter()
+        .filter(|edge| {
+            graph
+                .nodes
+                .iter()
+                .any(|n| n.id == edge.to && n.source == "registry")
+        })
+        .filter_map(|edge| {
+            let requirement = edge.requirement.clone()?;
+            let (status, [resolved, compatible_upgrade, incompatible_upgrade]) =
+                check(index, &edge.to, &requirement);
+
+            Some(Check {
+                name: edge.name.clone(),
+                package: edge.to.clone(),
+                kind: edge.kind,
+                target: edge.target.clone(),
+                requirement,
+                status,
+                resolved: resolved.map(|v| v.to_string()),
+                compatible_upgrade: compatible_upgrade.map(|v| v.to_string()),
+                incompatible_upgrade: incompatible_upgrade.map(|v| v.to_string()),
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,317 @@
+//! Acceptance rules for manifests, loaded once from `assets/day5_policy.toml`
+//! (or the file named by `DAY5_POLICY`). Without a policy file the only rule
+//! is the original one: `package.keywords` must contain "Christmas 2024".
+
+use std::{env, fmt, fs::read_to_string, io::ErrorKind, ops::Range, sync::OnceLock};
+
+use serde::Deserialize;
+use toml_edit::{Item, TableLike};
+
+use super::{lookup, workspace::DEPENDENCY_TABLES, ManifestSource, Problem, Severity};
+
+const POLICY: &str = "assets/day5_policy.toml";
+const MAGIC_KEYWORD: &str = "Christmas 2024";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    #[serde(default, rename = "rule")]
+    pub rules: Vec<Rule>,
+}
+
+/// One rule of a policy. `code` and `message` are what a violation is
+/// reported with.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Rule {
+    pub code: String,
+    pub message: String,
+    #[serde(default = "default_severity")]
+    pub severity: Severity,
+    #[serde(flatten)]
+    pub check: Check,
+}
+
+fn default_severity() -> Severity {
+    Severity::Error
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Check {
+    /// `package.keywords` must contain every one of `keywords`.
+    RequiredKeywords { keywords: Vec<String> },
+    /// `package.license` must be satisfiable with `licenses` alone.
+    AllowedLicenses { licenses: Vec<String> },
+    /// `package.rust-version` must be at least `version`.
+    MinRustVersion { version: RustVersion },
+    /// None of `dependencies` may be depended on, under any name.
+    BannedDependencies { dependencies: Vec<String> },
+    /// Every one of `tables` must be present under `package.metadata`.
+    RequiredMetadata { tables: Vec<String> },
+}
+
+/// A `rust-version` like "1.70" or "1.70.1", checked when the policy is
+/// read so a rule can't hold a version nothing compares against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(try_from = "String")]
+pub struct RustVersion(u64, u64, u64);
+
+impl TryFrom<String> for RustVersion {
+    type Error = String;
+
+    fn try_from(version: String) -> Result<Self, Self::Error> {
+        parse_version(&version).ok_or_else(|| format!("`{version}` is not a version like 1.70"))
+    }
+}
+
+impl fmt::Display for RustVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RustVersion(major, minor, 0) => write!(f, "{major}.{minor}"),
+            RustVersion(major, minor, patch) => write!(f, "{major}.{minor}.{patch}"),
+        }
+    }
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            rules: vec![Rule {
+                code: "magic-keyword-missing".to_string(),
+                message: "Magic keyword not provided".to_string(),
+                severity: Severity::Error,
+                check: Check::RequiredKeywords {
+                    keywords: vec![MAGIC_KEYWORD.to_string()],
+                },
+            }],
+        }
+    }
+}
+
+impl Policy {
+    /// Reads the policy file. A missing file means the default policy; a
+    /// broken one is an error, so a typo can't quietly switch the gate off.
+    pub fn load() -> Result<Self, String> {
+        let path = env::var("DAY5_POLICY").unwrap_or_else(|_| POLICY.to_string());
+        match read_to_string(&path) {
+            Ok(data) => toml::from_str(&data).map_err(|e| format!("{path}: {}", e.message())),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("{path}: {e}")),
+        }
+    }
+
+    /// The policy every manifest is checked against, read on first use.
+    pub fn shared() -> Result<&'static Self, &'static str> {
+        static SHARED: OnceLock<Result<Policy, String>> = OnceLock::new();
+        SHARED
+            .get_or_init(Self::load)
+            .as_ref()
+            .map_err(String::as_str)
+    }
+
+    pub(super) fn check(
+        &self,
+        root: &dyn TableLike,
+        source: &ManifestSource,
+        problems: &mut Vec<Problem>,
+    ) {
+        for rule in &self.rules {
+            for (path, span, detail) in rule.check.violations(root) {
+                problems.push(
+                    Problem::new(rule.severity, &rule.code, &path, &rule.message)
+                        .detail(detail)
+                        .at(source.position(span)),
+                );
+            }
+        }
+    }
+}
+
+/// Where a rule was broken and how.
+type Violation = (String, Option<Range<usize>>, String);
+
+fn missing(root: &dyn TableLike, path: &[&str], detail: String) -> Violation {
+    // Point at the closest table that does exist
+    let span = (1..path.len())
+        .rev()
+        .find_map(|n| lookup(root, &path[..n]).and_then(Item::span));
+    (path.join("."), span, detail)
+}
+
+impl Check {
+    fn violations(&self, root: &dyn TableLike) -> Vec<Violation> {
+        match self {
+            Check::RequiredKeywords { keywords } => required_keywords(root, keywords),
+            Check::AllowedLicenses { licenses } => allowed_licenses(root, licenses),
+            Check::MinRustVersion { version } => min_rust_version(root, version),
+            Check::BannedDependencies { dependencies } => banned_dependencies(root, dependencies),
+            Check::RequiredMetadata { tables } => tables
+                .iter()
+                .filter(|t| lookup(root, &["package", "metadata", t]).is_none())
+                .map(|t| {
+                    let path = ["package", "metadata", t.as_str()];
+                    missing(root, &path, format!("package.metadata.{t} is required"))
+                })
+                .collect(),
+        }
+    }
+}
+
+fn required_keywords(root: &dyn TableLike, required: &[String]) -> Vec<Violation> {
+    let path = ["package", "keywords"];
+    if lookup(root, &["package"]).is_none() {
+        return vec![missing(
+            root,
+            &path,
+            "manifest has no [package] table".to_string(),
+        )];
+    }
+    let Some((keywords, span)) = lookup(root, &path).and_then(|k| Some((k.as_array()?, k.span())))
+    else {
+        let detail = "package.keywords must be an array of strings".to_string();
+        return vec![missing(root, &path, detail)];
+    };
+
+    required
+        .iter()
+        .filter(|r| !keywords.iter().any(|k| k.as_str() == Some(r.as_str())))
+        .map(|r| {
+            let detail = format!("package.keywords must contain \"{r}\"");
+            (path.join("."), span.clone(), detail)
+        })
+        .collect()
+}
+
+/// Evaluates an SPDX license expression: true if it can be satisfied using
+/// only `allowed` licenses, or `None` if it isn't a valid expression. `WITH`
+/// exceptions go along with their license, and the legacy `/` separator
+/// means `OR`.
+pub fn spdx_allowed(expression: &str, allowed: &[String]) -> Option<bool> {
+    let spaced = expression
+        .replace('(', " ( ")
+        .replace(')', " ) ")
+        .replace('/', " OR ");
+    let tokens = spaced.split_whitespace().collect::<Vec<_>>();
+
+    fn or(tokens: &[&str], i: &mut usize, allowed: &[String]) -> Option<bool> {
+        let mut value = and(tokens, i, allowed)?;
+        while tokens.get(*i) == Some(&"OR") {
+            *i += 1;
+            value |= and(tokens, i, allowed)?;
+        }
+        Some(value)
+    }
+    fn and(tokens: &[&str], i: &mut usize, allowed: &[String]) -> Option<bool> {
+        let mut value = term(tokens, i, allowed)?;
+        while tokens.get(*i) == Some(&"AND") {
+            *i += 1;
+            value &= term(tokens, i, allowed)?;
+        }
+        Some(value)
+    }
+    fn term(tokens: &[&str], i: &mut usize, allowed: &[String]) -> Option<bool> {
+        let token = *tokens.get(*i)?;
+        *i += 1;
+        let value = match token {
+            "(" => {
+                let value = or(tokens, i, allowed)?;
+                (tokens.get(*i) == Some(&")")).then_some(())?;
+                *i += 1;
+                value
+            }
+            ")" | "AND" | "OR" | "WITH" => return None,
+            license => allowed.iter().any(|a| a == license.trim_end_matches('+')),
+        };
+        if tokens.get(*i) == Some(&"WITH") {
+            tokens.get(*i + 1)?;
+            *i += 2;
+        }
+        Some(value)
+    }
+
+    let mut i = 0;
+    let value = or(&tokens, &mut i, allowed)?;
+    (i == tokens.len()).then_some(value)
+}
+
+fn allowed_licenses(root: &dyn TableLike, allowed: &[String]) -> Vec<Violation> {
+    let path = ["package", "license"];
+    let Some(license) = lookup(root, &path) else {
+        return vec![missing(
+            root,
+            &path,
+            "package.license is required".to_string(),
+        )];
+    };
+    let detail = match license.as_str().map(|l| (l, spdx_allowed(l, allowed))) {
+        Some((_, Some(true))) => return vec![],
+        Some((l, Some(false))) => format!("{l} is not allowed, use {}", allowed.join(", ")),
+        _ => "package.license must be an SPDX license expression".to_string(),
+    };
+
+    vec![(path.join("."), license.span(), detail)]
+}
+
+fn parse_version(version: &str) -> Option<RustVersion> {
+    let mut parts = version.trim().split('.').map(|p| p.parse::<u64>());
+    let major = parts.next()?.ok()?;
+    let minor = parts.next().transpose().ok()?.unwrap_or(0);
+    let patch = parts.next().transpose().ok()?.unwrap_or(0);
+    parts
+        .next()
+        .is_none()
+        .then_some(RustVersion(major, minor, patch))
+}
+
+fn min_rust_version(root: &dyn TableLike, minimum: &RustVersion) -> Vec<Violation> {
+    let path = ["package", "rust-version"];
+    let Some(declared) = lookup(root, &path) else {
+        let detail = format!("package.rust-version is required, at least {minimum}");
+        return vec![missing(root, &path, detail)];
+    };
+    let detail = match declared.as_str().and_then(parse_version) {
+        Some(version) if version >= *minimum => return vec![],
+        Some(_) => format!("package.rust-version must be at least {minimum}"),
+        None => "package.rust-version must be a version like 1.70".to_string(),
+    };
+
+    vec![(path.join("."), declared.span(), detail)]
+}
+
+fn banned_dependencies(root: &dyn TableLike, banned: &[String]) -> Vec<Violation> {
+    let mut tables = DEPENDENCY_TABLES
+        .iter()
+        .map(|t| vec![t.to_string()])
+        .collect::<Vec<_>>();
+    if let Some(targets) = lookup(root, &["target"]).and_then(Item::as_table_like) {
+        for (target, _) in targets.iter() {
+            tables.extend(
+                DEPENDENCY_TABLES
+                    .iter()
+                    .map(|t| vec!["target".to_string(), target.to_string(), t.to_string()]),
+            );
+        }
+    }
+
+    let mut violations = vec![];
+    for table in tables {
+        let table = table.iter().map(String::as_str).collect::<Vec<_>>();
+        let Some(deps) = lookup(root, &table).and_then(Item::as_table_like) else {
+            continue;
+        };
+        for (name, item) in deps.iter() {
+            let package = item
+                .as_table_like()
+                .and_then(|t| t.get("package"))
+                .and_then(Item::as_str)
+                .unwrap_or(name);
+            if banned.iter().any(|b| b == package) {
+                let path = format!("{}.{name}", table.join("."));
+                let span = deps.get_key_value(name).and_then(|(k, _)| k.span());
+                violations.push((path, span, format!("{package} is a banned dependency")));
+            }
+        }
+    }
+
+    violations
+}
@@ -0,0 +1,301 @@
+//! Resolves `workspace = true` inheritance in member manifests against the
+//! workspace root, the way cargo does before it reads a member.
+//!
+//! Substituted values are written inline where the marker was, so every
+//! other line of the member keeps its original position. Paths inherited
+//! from the root (`path` dependencies, `readme`, `license-file`) are rebased
+//! onto the member's directory when both upload file names are known.
+
+use std::path::{Component, Path, PathBuf};
+
+use toml_edit::{DocumentMut, InlineTable, Item, TableLike, Value};
+
+pub(super) const DEPENDENCY_TABLES: [&str; 5] = [
+    "dependencies",
+    "dev-dependencies",
+    "dev_dependencies",
+    "build-dependencies",
+    "build_dependencies",
+];
+
+const PATH_FIELDS: [&str; 2] = ["readme", "license-file"];
+
+/// The inheritable parts of a workspace root manifest.
+#[derive(Debug, Clone)]
+pub struct Workspace {
+    package: Option<Item>,
+    dependencies: Option<Item>,
+    lints: Option<Item>,
+    dir: Option<PathBuf>,
+}
+
+/// An inheritance marker that could not be resolved.
+#[derive(Debug, Clone)]
+pub struct Unresolved {
+    pub path: Vec<String>,
+    pub reason: String,
+}
+
+impl Unresolved {
+    fn new(path: &[&str], reason: impl Into<String>) -> Self {
+        Self {
+            path: path.iter().map(|p| p.to_string()).collect(),
+            reason: reason.into(),
+        }
+    }
+}
+
+impl Workspace {
+    /// Reads the `[workspace]` table of a root manifest uploaded as `file`.
+    /// Returns `None` when the manifest is not a workspace root.
+    pub fn from_root(doc: &DocumentMut, file: Option<&str>) -> Option<Self> {
+        let workspace = doc.get("workspace")?.as_table_like()?;
+
+        Some(Self {
+            package: workspace.get("package").cloned(),
+            dependencies: workspace.get("dependencies").cloned(),
+            lints: workspace.get("lints").cloned(),
+            dir: file.map(directory),
+        })
+    }
+
+    fn field(&self, table: &str, key: &str) -> Option<&Item> {
+        let table = match table {
+            "package" => self.package.as_ref(),
+            _ => self.dependencies.as_ref(),
+        };
+        table?.as_table_like()?.get(key)
+    }
+
+    fn rebase(&self, member: Option<&Path>, path: &str) -> Option<String> {
+        let (root, member) = (self.dir.as_ref()?, member?);
+        if Path::new(path).is_absolute() {
+            return None;
+        }
+        let target = normalize(&root.join(path));
+        let member = normalize(member);
+
+        let common = target
+            .components()
+            .zip(member.components())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let mut relative = PathBuf::new();
+        member
+            .components()
+            .skip(common)
+            .for_each(|_| relative.push(".."));
+        target
+            .components()
+            .skip(common)
+            .for_each(|c| relative.push(c));
+
+        Some(relative.to_string_lossy().replace('\\', "/"))
+    }
+}
+
+/// The directory part of an uploaded file name.
+pub fn directory(file: &str) -> PathBuf {
+    Path::new(file)
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default()
+}
+
+fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if out.file_name().is_some() => {
+                out.pop();
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn inherits(item: &Item) -> bool {
+    item.as_table_like()
+        .and_then(|t| t.get("workspace"))
+        .and_then(Item::as_bool)
+        .unwrap_or(false)
+}
+
+/// Writes `value` over `item`, keeping any comment that trailed the marker.
+fn replace(item: &mut Item, mut value: Value) {
+    match &mut value {
+        Value::Array(array) => array.fmt(),
+        Value::InlineTable(table) => table.fmt(),
+        _ => {}
+    }
+    let decor = item.as_value().map(|v| v.decor().clone());
+    *value.decor_mut() = decor.unwrap_or_default();
+    *item = Item::Value(value);
+}
+
+fn resolve_package(
+    package: &mut dyn TableLike,
+    workspace: Option<&Workspace>,
+    member: Option<&Path>,
+    unresolved: &mut Vec<Unresolved>,
+) {
+    for (key, item) in package.iter_mut() {
+        if !inherits(item) {
+            continue;
+        }
+        let key = key.get();
+        let Some(workspace) = workspace else {
+            unresolved.push(Unresolved::new(
+                &["package", key],
+                "inherits from a workspace, but no workspace root was uploaded",
+            ));
+            continue;
+        };
+        let Some(value) = workspace
+            .field("package", key)
+            .and_then(|v| v.clone().into_value().ok())
+        else {
+            unresolved.push(Unresolved::new(
+                &["package", key],
+                format!("workspace.package.{key} is not defined"),
+            ));
+            continue;
+        };
+
+        let rebased = match (PATH_FIELDS.contains(&key), value.as_str()) {
+            (true, Some(path)) => workspace.rebase(member, path).map(Value::from),
+            _ => None,
+        };
+        replace(item, rebased.unwrap_or(value));
+    }
+}
+
+fn resolve_dependency(
+    name: &str,
+    declared: &dyn TableLike,
+    workspace: &Workspace,
+    member: Option<&Path>,
+) -> Option<Value> {
+    let mut resolved = match workspace.field("dependencies", name)? {
+        Item::Value(Value::String(version)) => {
+            let mut table = InlineTable::new();
+            table.insert("version", Value::from(version.value().as_str()));
+            table
+        }
+        other => match other.clone().into_value().ok()? {
+            Value::InlineTable(table) => table,
+            _ => return None,
+        },
+    };
+
+    if let Some(path) = resolved.get("path").and_then(Value::as_str) {
+        if let Some(path) = workspace.rebase(member, path) {
+            resolved.insert("path", Value::from(path));
+        }
+    }
+    for (key, item) in declared.iter() {
+        let Some(value) = item.as_value() else {
+            continue;
+        };
+        match key {
+            "workspace" => {}
+            // Features named by the member are added to the inherited ones
+            "features" => {
+                let mut features = resolved
+                    .get("features")
+                    .and_then(Value::as_array)
+                    .cloned()
+                    .unwrap_or_default();
+                for feature in value.as_array().into_iter().flatten() {
+                    if !features.iter().any(|f| f.as_str() == feature.as_str()) {
+                        features.push(feature.clone());
+                    }
+                }
+                resolved.insert("features", Value::Array(features));
+            }
+            key => {
+                resolved.insert(key, value.clone());
+            }
+        }
+    }
+
+    Some(Value::InlineTable(resolved))
+}
+
+fn resolve_dependencies(
+    deps: &mut dyn TableLike,
+    prefix: &[&str],
+    workspace: Option<&Workspace>,
+    member: Option<&Path>,
+    unresolved: &mut Vec<Unresolved>,
+) {
+    for (name, item) in deps.iter_mut() {
+        if !inherits(item) {
+            continue;
+        }
+        let name = name.get();
+        let path = [prefix, &[name]].concat();
+        let resolved = match (workspace, item.as_table_like()) {
+            (Some(workspace), Some(declared)) => {
+                resolve_dependency(name, declared, workspace, member)
+                    .ok_or_else(|| format!("workspace.dependencies.{name} is not defined"))
+            }
+            _ => Err("inherits from a workspace, but no workspace root was uploaded".to_string()),
+        };
+
+        match resolved {
+            Ok(value) => replace(item, value),
+            Err(reason) => unresolved.push(Unresolved::new(&path, reason)),
+        }
+    }
+}
+
+/// Resolves every inheritance marker in `doc`, a member manifest uploaded
+/// from the directory `member`. Whatever can't be resolved is left as it
+/// was and reported.
+pub fn resolve(
+    doc: &mut DocumentMut,
+    workspace: Option<&Workspace>,
+    member: Option<&Path>,
+) -> Vec<Unresolved> {
+    let mut unresolved = vec![];
+
+    if let Some(package) = doc.get_mut("package").and_then(Item::as_table_like_mut) {
+        resolve_package(package, workspace, member, &mut unresolved);
+    }
+
+    for table in DEPENDENCY_TABLES {
+        if let Some(deps) = doc.get_mut(table).and_then(Item::as_table_like_mut) {
+            resolve_dependencies(deps, &[table], workspace, member, &mut unresolved);
+        }
+    }
+    if let Some(targets) = doc.get_mut("target").and_then(Item::as_table_like_mut) {
+        for (target, platform) in targets.iter_mut() {
+            let Some(platform) = platform.as_table_like_mut() else {
+                continue;
+            };
+            for table in DEPENDENCY_TABLES {
+                if let Some(deps) = platform.get_mut(table).and_then(Item::as_table_like_mut) {
+                    let prefix = ["target", target.get(), table];
+                    resolve_dependencies(deps, &prefix, workspace, member, &mut unresolved);
+                }
+            }
+        }
+    }
+
+    if let Some(lints) = doc.get_mut("lints") {
+        if inherits(lints) {
+            match workspace.and_then(|w| w.lints.clone()) {
+                Some(inherited) => *lints = inherited,
+                None => unresolved.push(Unresolved::new(
+                    &["lints"],
+                    "workspace.lints is not defined",
+                )),
+            }
+        }
+    }
+
+    unresolved
+}
@@ -1,36 +1,33 @@
-use std::{
-    sync::{Arc, Mutex},
-    time::Duration,
-};
+use std::net::SocketAddr;
 
 use axum::{
-    extract::State,
+    extract::{ConnectInfo, Query, State},
     http::{HeaderMap, StatusCode},
     response::{IntoResponse, Response},
-    routing::post,
+    routing::{get, post},
     Json, Router,
 };
-use leaky_bucket::RateLimiter;
+use layer::{RateLimit, RateLimitLayer};
 use serde::{Deserialize, Serialize};
+use units::Catalogue;
 
-pub struct CustomLimiter {
-    pub rate_limiter: RateLimiter,
-}
+pub mod clients;
+pub mod config;
+pub mod layer;
+pub mod limiter;
+pub mod store;
+pub mod units;
+
+/// `limits` is shared with whatever else the caller rate limits.
+pub fn day_9_routes(limits: RateLimit) -> Router {
+    let milk_limit = limits.layer("milk").all().message("No milk available\n");
 
-pub fn day_9_routes() -> Router {
     Router::new()
-        .route("/milk", post(milk))
+        .route("/milk", post(milk).layer(milk_limit.clone()))
         .route("/refill", post(refill))
-        .with_state(Arc::new(Mutex::new(build_milk_limiter())))
-}
-
-pub fn build_milk_limiter() -> RateLimiter {
-    RateLimiter::builder()
-        .max(5)
-        .initial(5)
-        .interval(Duration::from_secs(1))
-        .refill(1)
-        .build()
+        .route("/convert", get(convert))
+        .route("/units", get(units))
+        .with_state(milk_limit)
 }
 
 #[derive(Debug, Clone, Copy, Deserialize, Serialize, Default)]
@@ -45,68 +42,104 @@ pub struct Day9Json {
     pub pints: Option<f32>,
 }
 
-pub async fn milk(
-    State(rate_limiter): State<Arc<Mutex<RateLimiter>>>,
-    headers: HeaderMap,
-    payload: Option<Json<Day9Json>>,
-) -> Response {
-    println!("\nHeaders: {:#?}\nPayload: {:#?}", headers, payload);
-    match rate_limiter.lock().unwrap().try_acquire(1) {
-        true => {
-            if let Some(content_type) = headers.get("Content-Type") {
-                if content_type == "application/json" {
-                    if let Some(payload) = payload {
-                        match (
-                            payload.liters,
-                            payload.gallons,
-                            payload.litres,
-                            payload.pints,
-                        ) {
-                            (Some(liters), None, None, None) => {
-                                let result = Day9Json {
-                                    gallons: Some(liters * 0.26417205),
-                                    ..Day9Json::default()
-                                };
-                                (StatusCode::OK, Json(result)).into_response()
-                            }
-                            (None, Some(gallons), None, None) => {
-                                let result = Day9Json {
-                                    liters: Some(gallons / 0.26417205),
-                                    ..Day9Json::default()
-                                };
-                                (StatusCode::OK, Json(result)).into_response()
-                            }
-                            (None, None, Some(litres), None) => {
-                                let result = Day9Json {
-                                    pints: Some(litres * 1.759754),
-                                    ..Day9Json::default()
-                                };
-                                (StatusCode::OK, Json(result)).into_response()
-                            }
-                            (None, None, None, Some(pints)) => {
-                                let result = Day9Json {
-                                    litres: Some(pints / 1.759754),
-                                    ..Day9Json::default()
-                                };
-                                (StatusCode::OK, Json(result)).into_response()
-                            }
-                            _ => (StatusCode::BAD_REQUEST).into_response(),
-                        }
-                    } else {
-                        (StatusCode::BAD_REQUEST, "").into_response()
-                    }
-                } else {
-                    (StatusCode::OK, "Milk withdrawn\n").into_response()
+/// Each field of [`Day9Json`], the unit it is given in, and the field it is
+/// converted to.
+const MILK_FIELDS: [(&str, &str, &str); 4] = [
+    ("liters", "litre", "gallons"),
+    ("gallons", "us_gallon", "liters"),
+    ("litres", "litre", "pints"),
+    ("pints", "imperial_pint", "litres"),
+];
+
+impl Day9Json {
+    fn field(&mut self, name: &str) -> &mut Option<f32> {
+        match name {
+            "liters" => &mut self.liters,
+            "gallons" => &mut self.gallons,
+            "litres" => &mut self.litres,
+            "pints" => &mut self.pints,
+            _ => unreachable!("no milk field {name}"),
+        }
+    }
+
+    /// Converts the one field that is set into its counterpart. Nothing
+    /// comes back if no field or more than one is set.
+    fn convert(mut self) -> Option<Day9Json> {
+        let mut set = MILK_FIELDS
+            .iter()
+            .filter_map(|&(field, unit, to)| Some(((*self.field(field))?, unit, to)));
+        let (value, from, to) = set.next()?;
+        if set.next().is_some() {
+            return None;
+        }
+
+        let (_, to_unit, _) = MILK_FIELDS.iter().find(|(field, ..)| *field == to)?;
+        let value = Catalogue::builtin()
+            .convert(value as f64, from, to_unit)
+            .ok()?;
+        let mut result = Day9Json::default();
+        *result.field(to) = Some(value as f32);
+        Some(result)
+    }
+}
+
+pub async fn milk(headers: HeaderMap, payload: Option<Json<Day9Json>>) -> Response {
+    tracing::debug!("milk payload: {payload:?}");
+    if let Some(content_type) = headers.get("Content-Type") {
+        if content_type == "application/json" {
+            if let Some(payload) = payload {
+                match payload.convert() {
+                    Some(result) => (StatusCode::OK, Json(result)).into_response(),
+                    None => (StatusCode::BAD_REQUEST).into_response(),
                 }
             } else {
-                (StatusCode::OK, "Milk withdrawn\n").into_response()
+                (StatusCode::BAD_REQUEST, "").into_response()
             }
+        } else {
+            (StatusCode::OK, "Milk withdrawn\n").into_response()
         }
-        false => (StatusCode::TOO_MANY_REQUESTS, "No milk available\n").into_response(),
+    } else {
+        (StatusCode::OK, "Milk withdrawn\n").into_response()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConvertParams {
+    pub from: String,
+    pub to: String,
+    pub value: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Conversion {
+    pub from: String,
+    pub to: String,
+    pub value: f64,
+    pub result: f64,
+}
+
+pub async fn convert(Query(params): Query<ConvertParams>) -> Response {
+    match Catalogue::builtin().convert(params.value, &params.from, &params.to) {
+        Ok(result) => Json(Conversion {
+            from: params.from,
+            to: params.to,
+            value: params.value,
+            result,
+        })
+        .into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
     }
 }
 
-pub async fn refill(State(limiter): State<Arc<Mutex<RateLimiter>>>) -> impl IntoResponse {
-    *limiter.lock().unwrap() = build_milk_limiter();
+pub async fn units() -> Json<&'static Catalogue> {
+    Json(Catalogue::builtin())
+}
+
+pub async fn refill(
+    State(milk_limit): State<RateLimitLayer>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    milk_limit.refill(&headers, peer.map(|p| p.0)).await;
     (StatusCode::OK, "")
 }
@@ -0,0 +1,340 @@
+//! Per-client milk buckets. Which part of a request identifies the client
+//! is set with `DAY9_CLIENT_KEY`: `ip` (the default), `api-key` for the
+//! `X-Api-Key` header, or `jwt-sub` for the subject of a bearer token signed
+//! with the RSA public key in the PEM file `DAY9_JWT_KEY` names. Tokens must
+//! carry an `exp` claim and be signed with one of the comma separated
+//! algorithms in `DAY9_JWT_ALGORITHMS` (`RS256` by default).
+//!
+//! IPv6 clients are keyed by their /64, as that is what one host is usually
+//! handed, and anything finer would give it as many buckets as it likes.
+//!
+//! Behind proxies, `DAY9_TRUSTED_PROXIES` says how many of them append to
+//! `X-Forwarded-For`; the client's ip is the hop the outermost one added.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    env,
+    fs::read,
+    net::{IpAddr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use axum::http::{header::AUTHORIZATION, HeaderMap};
+use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use tokio::sync::Notify;
+
+use super::{
+    config::{LimiterConfig, Limits},
+    limiter::{Clock, MilkLimiter, Quota},
+};
+
+/// How often buckets that have gone back to a fresh state are dropped.
+pub(super) const SWEEP_EVERY: Duration = Duration::from_secs(10);
+/// The most buckets kept at once. Past this, clients without a bucket
+/// share [`OVERFLOW`] until idle buckets are swept.
+pub const MAX_CLIENTS: usize = 10_000;
+/// The bucket for clients there was no room for, with the default limit.
+pub const OVERFLOW: &str = "overflow";
+
+#[derive(Clone)]
+pub enum ClientKey {
+    PeerIp,
+    /// `X-Api-Key`, for keys the limits file has a limit for.
+    ApiKey,
+    /// The subject of a bearer token signed with this key, and checked
+    /// against this validation.
+    JwtSubject(DecodingKey, Box<Validation>),
+}
+
+#[derive(Deserialize)]
+struct Subject {
+    sub: String,
+}
+
+impl ClientKey {
+    pub fn from_env() -> Self {
+        match env::var("DAY9_CLIENT_KEY").as_deref() {
+            Err(_) | Ok("ip") => ClientKey::PeerIp,
+            Ok("api-key") => ClientKey::ApiKey,
+            Ok("jwt-sub") => match jwt_key().and_then(|key| Ok((key, jwt_algorithms()?))) {
+                Ok((key, algorithms)) => ClientKey::jwt_subject(key, &algorithms),
+                Err(e) => {
+                    tracing::warn!("no key to verify jwts with, keying clients by ip: {e}");
+                    ClientKey::PeerIp
+                }
+            },
+            Ok(other) => {
+                tracing::warn!("unknown DAY9_CLIENT_KEY {other:?}, keying clients by ip");
+                ClientKey::PeerIp
+            }
+        }
+    }
+
+    /// Keys clients by the subject of bearer tokens signed with `key` using
+    /// one of `algorithms`. Tokens without an `exp` claim are not accepted.
+    pub fn jwt_subject(key: DecodingKey, algorithms: &[Algorithm]) -> Self {
+        let mut validation = Validation::default();
+        validation.algorithms = algorithms.to_vec();
+        validation.validate_aud = false;
+        ClientKey::JwtSubject(key, Box::new(validation))
+    }
+
+    /// The bucket a request from `ip` draws from. Requests without a key
+    /// this can vouch for fall back to their ip, so that making one up
+    /// doesn't earn a fresh bucket.
+    pub fn identify(&self, headers: &HeaderMap, ip: Option<IpAddr>, limits: &Limits) -> String {
+        let key = match self {
+            ClientKey::PeerIp => None,
+            ClientKey::ApiKey => headers
+                .get("x-api-key")
+                .and_then(|k| k.to_str().ok())
+                .map(|k| format!("key:{k}"))
+                .filter(|client| limits.clients.contains_key(client)),
+            ClientKey::JwtSubject(key, validation) => headers
+                .get(AUTHORIZATION)
+                .and_then(|a| a.to_str().ok())
+                .and_then(|a| a.strip_prefix("Bearer "))
+                .and_then(|token| decode::<Subject>(token, key, validation).ok())
+                .map(|token| format!("sub:{}", token.claims.sub)),
+        };
+
+        key.unwrap_or_else(|| match ip.map(|ip| ip.to_canonical()) {
+            Some(IpAddr::V4(ip)) => format!("ip:{ip}"),
+            Some(IpAddr::V6(ip)) => {
+                let prefix = Ipv6Addr::from(ip.to_bits() & !(u64::MAX as u128));
+                format!("ip:{prefix}/64")
+            }
+            None => "ip:unknown".to_string(),
+        })
+    }
+}
+
+fn jwt_key() -> Result<DecodingKey, String> {
+    let path = env::var("DAY9_JWT_KEY").map_err(|_| "DAY9_JWT_KEY is not set".to_string())?;
+    let pem = read(&path).map_err(|e| format!("{path}: {e}"))?;
+    DecodingKey::from_rsa_pem(&pem).map_err(|e| format!("{path}: {e}"))
+}
+
+fn jwt_algorithms() -> Result<Vec<Algorithm>, String> {
+    let Ok(names) = env::var("DAY9_JWT_ALGORITHMS") else {
+        return Ok(vec![Algorithm::RS256]);
+    };
+    names
+        .split(',')
+        .map(|name| {
+            name.trim()
+                .parse()
+                .map_err(|_| format!("DAY9_JWT_ALGORITHMS: unknown algorithm {name:?}"))
+        })
+        .collect()
+}
+
+/// The client's ip. With no proxies in front that is the socket peer;
+/// otherwise it is the `X-Forwarded-For` hop the outermost of
+/// `trusted_proxies` added, as hops further left are whatever the client
+/// sent. A request that didn't pass through them all is taken at its peer.
+pub fn client_ip(
+    headers: &HeaderMap,
+    peer: Option<SocketAddr>,
+    trusted_proxies: usize,
+) -> Option<IpAddr> {
+    let peer = peer.map(|p| p.ip());
+    if trusted_proxies == 0 {
+        return peer;
+    }
+    let hops = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|f| f.to_str().ok())
+        .flat_map(|f| f.split(','))
+        .map(str::trim)
+        .collect::<Vec<_>>();
+
+    hops.len()
+        .checked_sub(trusted_proxies)
+        .and_then(|i| hops[i].parse().ok())
+        .or(peer)
+}
+
+struct Bucket {
+    config: LimiterConfig,
+    limiter: Box<dyn MilkLimiter>,
+    /// Tickets of the requests waiting for this bucket, oldest first.
+    waiting: VecDeque<u64>,
+    /// Woken whenever the queue moves.
+    moved: Arc<Notify>,
+}
+
+pub struct Clients {
+    limits: Limits,
+    clock: Arc<dyn Clock>,
+    buckets: HashMap<String, Bucket>,
+    swept: Instant,
+    next_ticket: u64,
+}
+
+impl Clients {
+    pub fn new(limits: Limits, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            limits,
+            swept: clock.now(),
+            clock,
+            buckets: HashMap::new(),
+            next_ticket: 0,
+        }
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// The bucket `name` draws from: its own, or [`OVERFLOW`] if it has
+    /// none and there is no room for one.
+    fn bucket(&mut self, name: &str, config: &LimiterConfig) -> (&mut Bucket, Instant) {
+        let now = self.clock.now();
+        if now.duration_since(self.swept) >= SWEEP_EVERY {
+            self.sweep(now);
+        }
+
+        let (name, config) = match self.buckets.contains_key(name) {
+            false if self.buckets.len() >= MAX_CLIENTS => (OVERFLOW, &self.limits.default),
+            _ => (name, config),
+        };
+        let bucket = self
+            .buckets
+            .entry(name.to_string())
+            .or_insert_with(|| Bucket {
+                config: config.clone(),
+                limiter: config.build(now),
+                waiting: VecDeque::new(),
+                moved: Arc::new(Notify::new()),
+            });
+        (bucket, now)
+    }
+
+    /// Draws from `client`'s bucket, with the limit the limits file gives
+    /// the client.
+    pub fn try_acquire(&mut self, client: &str) -> Quota {
+        let config = self.limits.for_client(client).clone();
+        self.try_acquire_with(client, &config)
+    }
+
+    /// Draws from `bucket`, making it with `config` if it doesn't exist.
+    /// Nothing is drawn while requests are queued for the bucket, so that
+    /// they are served first.
+    pub fn try_acquire_with(&mut self, bucket: &str, config: &LimiterConfig) -> Quota {
+        let (bucket, now) = self.bucket(bucket, config);
+        match bucket.waiting.is_empty() {
+            true => bucket.limiter.try_acquire(now),
+            false => Quota {
+                allowed: false,
+                ..bucket.limiter.peek(now)
+            },
+        }
+    }
+
+    /// Joins the queue for `bucket`. Returns the ticket to draw with, and
+    /// what to wait on for the queue to move.
+    pub fn enqueue(&mut self, bucket: &str, config: &LimiterConfig) -> (u64, Arc<Notify>) {
+        let ticket = self.next_ticket;
+        self.next_ticket += 1;
+        let (bucket, _) = self.bucket(bucket, config);
+        bucket.waiting.push_back(ticket);
+        (ticket, bucket.moved.clone())
+    }
+
+    /// Draws for `ticket` if it is first in the queue, leaving the queue if
+    /// that succeeds. Also says whether the ticket was first.
+    pub fn try_acquire_queued(
+        &mut self,
+        bucket: &str,
+        config: &LimiterConfig,
+        ticket: u64,
+    ) -> (Quota, bool) {
+        let now = self.clock.now();
+        let bucket = match self.queued(bucket, ticket) {
+            Some(name) => self.buckets.get_mut(&name).unwrap(),
+            None => self.bucket(bucket, config).0,
+        };
+        if bucket.waiting.front() != Some(&ticket) {
+            let quota = bucket.limiter.peek(now);
+            return (
+                Quota {
+                    allowed: false,
+                    ..quota
+                },
+                false,
+            );
+        }
+
+        let quota = bucket.limiter.try_acquire(now);
+        if quota.allowed {
+            bucket.waiting.pop_front();
+            bucket.moved.notify_waiters();
+        }
+        (quota, true)
+    }
+
+    /// Leaves the queue for `bucket` without drawing, because the request
+    /// gave up or its client went away.
+    pub fn dequeue(&mut self, bucket: &str, ticket: u64) {
+        let Some(bucket) = self
+            .queued(bucket, ticket)
+            .and_then(|name| self.buckets.get_mut(&name))
+        else {
+            return;
+        };
+        if let Some(place) = bucket.waiting.iter().position(|t| *t == ticket) {
+            bucket.waiting.remove(place);
+            if place == 0 {
+                bucket.moved.notify_waiters();
+            }
+        }
+    }
+
+    /// Which bucket `ticket` is queued in, `bucket` or [`OVERFLOW`].
+    fn queued(&self, bucket: &str, ticket: u64) -> Option<String> {
+        [bucket, OVERFLOW]
+            .into_iter()
+            .find(|name| {
+                self.buckets
+                    .get(*name)
+                    .is_some_and(|b| b.waiting.contains(&ticket))
+            })
+            .map(str::to_string)
+    }
+
+    /// Gives `bucket` its full allowance again.
+    pub fn refill(&mut self, name: &str) {
+        let now = self.clock.now();
+        let Some(bucket) = self.buckets.get_mut(name) else {
+            return;
+        };
+        match bucket.waiting.is_empty() {
+            true => {
+                self.buckets.remove(name);
+            }
+            false => {
+                bucket.limiter = bucket.config.build(now);
+                bucket.moved.notify_waiters();
+            }
+        }
+    }
+
+    /// How many buckets there are.
+    pub fn len(&self) -> usize {
+        self.buckets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buckets.is_empty()
+    }
+
+    fn sweep(&mut self, now: Instant) {
+        self.swept = now;
+        self.buckets
+            .retain(|_, bucket| !bucket.waiting.is_empty() || !bucket.limiter.is_idle(now));
+    }
+}
@@ -0,0 +1,132 @@
+//! Which limiter a client gets, read from `assets/day9_limits.toml` (or the
+//! file named by `DAY9_LIMITS`). Without a file every client gets the
+//! original bucket: 5 milk, refilled by 1 every second.
+
+use std::{
+    collections::HashMap,
+    env,
+    fs::read_to_string,
+    io::ErrorKind,
+    num::{NonZeroU64, NonZeroUsize},
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+
+use super::limiter::{FixedWindow, Gcra, MilkLimiter, SlidingWindowLog, TokenBucket};
+
+const LIMITS: &str = "assets/day9_limits.toml";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(
+    tag = "algorithm",
+    rename_all = "kebab-case",
+    rename_all_fields = "kebab-case",
+    deny_unknown_fields
+)]
+pub enum LimiterConfig {
+    TokenBucket {
+        capacity: NonZeroUsize,
+        refill: NonZeroUsize,
+        interval_ms: NonZeroU64,
+    },
+    FixedWindow {
+        limit: NonZeroUsize,
+        window_ms: NonZeroU64,
+    },
+    SlidingWindowLog {
+        limit: NonZeroUsize,
+        window_ms: NonZeroU64,
+    },
+    Gcra {
+        burst: NonZeroUsize,
+        interval_ms: NonZeroU64,
+    },
+}
+
+impl Default for LimiterConfig {
+    fn default() -> Self {
+        LimiterConfig::TokenBucket {
+            capacity: NonZeroUsize::new(5).unwrap(),
+            refill: NonZeroUsize::new(1).unwrap(),
+            interval_ms: NonZeroU64::new(1000).unwrap(),
+        }
+    }
+}
+
+fn millis(ms: NonZeroU64) -> Duration {
+    Duration::from_millis(ms.get())
+}
+
+impl LimiterConfig {
+    pub fn build(&self, now: Instant) -> Box<dyn MilkLimiter> {
+        match *self {
+            LimiterConfig::TokenBucket {
+                capacity,
+                refill,
+                interval_ms,
+            } => Box::new(TokenBucket::new(
+                capacity.get(),
+                refill.get(),
+                millis(interval_ms),
+                now,
+            )),
+            LimiterConfig::FixedWindow { limit, window_ms } => {
+                Box::new(FixedWindow::new(limit.get(), millis(window_ms), now))
+            }
+            LimiterConfig::SlidingWindowLog { limit, window_ms } => {
+                Box::new(SlidingWindowLog::new(limit.get(), millis(window_ms)))
+            }
+            LimiterConfig::Gcra { burst, interval_ms } => {
+                Box::new(Gcra::new(burst.get(), millis(interval_ms), now))
+            }
+        }
+    }
+}
+
+/// The limiter for everyone, and the ones for particular clients and
+/// routes. Clients are named the way they are keyed: `ip:<address>`, or
+/// `ip:<prefix>/64` for IPv6, `key:<api key>` or `sub:<subject>`. Routes are named after the layer
+/// that limits them, as `<layer> <METHOD> <path>`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Limits {
+    #[serde(default)]
+    pub default: LimiterConfig,
+    #[serde(default)]
+    pub clients: HashMap<String, LimiterConfig>,
+    #[serde(default)]
+    pub routes: HashMap<String, LimiterConfig>,
+}
+
+impl Limits {
+    /// Reads the limits file. A missing file means the defaults; a broken
+    /// one is logged and also means the defaults, so milk keeps flowing.
+    pub fn load() -> Self {
+        let path = env::var("DAY9_LIMITS").unwrap_or_else(|_| LIMITS.to_string());
+        let data = match read_to_string(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Self::default(),
+            Err(e) => {
+                tracing::warn!("ignoring {path}: {e}");
+                return Self::default();
+            }
+        };
+
+        toml::from_str(&data).unwrap_or_else(|e: toml::de::Error| {
+            tracing::warn!("ignoring {path}: {}", e.message());
+            Self::default()
+        })
+    }
+
+    pub fn for_client(&self, client: &str) -> &LimiterConfig {
+        self.clients.get(client).unwrap_or(&self.default)
+    }
+
+    /// A route's own limit, or the client's if the route has none.
+    pub fn for_route(&self, route: &str, client: &str) -> &LimiterConfig {
+        self.routes
+            .get(route)
+            .unwrap_or_else(|| self.for_client(client))
+    }
+}
@@ -0,0 +1,395 @@
+//! The milk limiter as a tower layer, so any router can be rate limited.
+//! All layers made from one [`RateLimit`] share its buckets, which keeps a
+//! client's allowance in one place however many routes it is spread over.
+//! A request that sends `Prefer: wait=<seconds>` queues for its turn, up to
+//! that long, instead of being turned away at once.
+//!
+//! With `DAY9_LIMITER_STORE=postgres` the buckets are kept in the database
+//! instead, shared by every instance. Instances can't queue requests in
+//! order between them, so `Prefer: wait` is then ignored: every request is
+//! answered at once, and no `Preference-Applied` is sent.
+//!
+//! ```ignore
+//! let limits = RateLimit::from_env(&pool);
+//! let quotes = quote_routes().layer(
+//!     limits
+//!         .layer("quotes")
+//!         .route(Method::POST, "/draft")
+//!         .route(Method::PUT, "/undo/:id"),
+//! );
+//! ```
+
+use std::{
+    env,
+    future::Future,
+    net::SocketAddr,
+    pin::{pin, Pin},
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use axum::{
+    extract::{ConnectInfo, Request},
+    http::{HeaderMap, HeaderValue, Method, StatusCode},
+    response::{IntoResponse, Response},
+};
+use sqlx::PgPool;
+use tokio::time::{sleep_until, Instant};
+use tower::{Layer, Service};
+
+use super::{
+    clients::{client_ip, ClientKey, Clients},
+    config::{LimiterConfig, Limits},
+    limiter::{Quota, SystemClock},
+    store::PgBuckets,
+};
+
+/// The longest a request may ask to wait for its turn.
+const MAX_WAIT: Duration = Duration::from_secs(30);
+
+/// The client buckets every layer made from this draws from.
+#[derive(Clone)]
+pub struct RateLimit {
+    key: ClientKey,
+    /// How many proxies in front of the service append to
+    /// `X-Forwarded-For`.
+    trusted_proxies: usize,
+    clients: Arc<Mutex<Clients>>,
+    /// Where the buckets are kept if not in `clients`. `clients` still
+    /// has the limits, and the buckets while the database is unreachable.
+    store: Option<PgBuckets>,
+}
+
+impl RateLimit {
+    pub fn new(key: ClientKey, clients: Clients) -> Self {
+        Self {
+            key,
+            trusted_proxies: 0,
+            clients: Arc::new(Mutex::new(clients)),
+            store: None,
+        }
+    }
+
+    /// Takes clients' ips from `X-Forwarded-For`, as appended by this many
+    /// proxies, rather than from the socket peer.
+    pub fn trusted_proxies(mut self, proxies: usize) -> Self {
+        self.trusted_proxies = proxies;
+        self
+    }
+
+    /// Keeps the buckets in `store` rather than in memory.
+    pub fn store(mut self, store: PgBuckets) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Keys clients by `DAY9_CLIENT_KEY` behind `DAY9_TRUSTED_PROXIES`
+    /// proxies, with limits from the limits file. The buckets are kept in
+    /// `pool` if `DAY9_LIMITER_STORE` is `postgres` and in memory if it is
+    /// `memory` or unset.
+    pub fn from_env(pool: &PgPool) -> Self {
+        let proxies = match env::var("DAY9_TRUSTED_PROXIES").map(|p| p.parse::<usize>()) {
+            Err(_) => 0,
+            Ok(Ok(proxies)) => proxies,
+            Ok(Err(e)) => {
+                tracing::warn!("ignoring DAY9_TRUSTED_PROXIES: {e}");
+                0
+            }
+        };
+        let limit = Self::new(
+            ClientKey::from_env(),
+            Clients::new(Limits::load(), Arc::new(SystemClock)),
+        )
+        .trusted_proxies(proxies);
+        match env::var("DAY9_LIMITER_STORE").as_deref() {
+            Err(_) | Ok("memory") => limit,
+            Ok("postgres") => limit.store(PgBuckets::new(pool.clone())),
+            Ok(other) => {
+                tracing::warn!("unknown DAY9_LIMITER_STORE {other:?}, keeping buckets in memory");
+                limit
+            }
+        }
+    }
+
+    /// A layer that limits nothing until told which routes to limit.
+    /// `name` keeps its buckets apart from other layers'.
+    pub fn layer(&self, name: &str) -> RateLimitLayer {
+        RateLimitLayer {
+            limit: self.clone(),
+            name: name.to_string(),
+            routes: Arc::new(vec![]),
+            all: false,
+            message: "Too many requests\n",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct RouteLimit {
+    method: Method,
+    path: String,
+    /// How the route is named in the limits file: `<METHOD> <path>`.
+    name: String,
+}
+
+/// Whether `path` is matched by `pattern`, written the way axum routes are:
+/// `:name` matches any one segment and `*name` everything after it.
+fn route_matches(pattern: &str, path: &str) -> bool {
+    let mut path = path.trim_end_matches('/').split('/');
+    for segment in pattern.trim_end_matches('/').split('/') {
+        if segment.starts_with('*') {
+            return true;
+        }
+        match path.next() {
+            Some(p) if p == segment || (segment.starts_with(':') && !p.is_empty()) => {}
+            _ => return false,
+        }
+    }
+    path.next().is_none()
+}
+
+#[derive(Clone)]
+pub struct RateLimitLayer {
+    limit: RateLimit,
+    name: String,
+    routes: Arc<Vec<RouteLimit>>,
+    all: bool,
+    message: &'static str,
+}
+
+impl RateLimitLayer {
+    /// Limits `method` requests to `path`, each route with a quota of its
+    /// own: `routes."<layer> <METHOD> <path>"` in the limits file, or the
+    /// client's limit if that isn't set.
+    pub fn route(mut self, method: Method, path: &str) -> Self {
+        Arc::make_mut(&mut self.routes).push(RouteLimit {
+            name: format!("{method} {path}"),
+            method,
+            path: path.to_string(),
+        });
+        self
+    }
+
+    /// Limits every request no route matches, with the client's limit.
+    pub fn all(mut self) -> Self {
+        self.all = true;
+        self
+    }
+
+    /// The body of a 429 response.
+    pub fn message(mut self, message: &'static str) -> Self {
+        self.message = message;
+        self
+    }
+
+    /// Gives the client a full allowance again on every route this layer
+    /// limits.
+    pub async fn refill(&self, headers: &HeaderMap, peer: Option<SocketAddr>) {
+        let client = self.limit.identify(headers, peer);
+        let buckets = std::iter::once(format!("{} {client}", self.name)).chain(
+            self.routes
+                .iter()
+                .map(|route| format!("{} {} {client}", self.name, route.name)),
+        );
+        for bucket in buckets {
+            self.limit.refill(&bucket).await;
+        }
+    }
+
+    /// The bucket `request` draws from, and the limit to make it with, if
+    /// the request is limited at all.
+    fn bucket(&self, request: &Request) -> Option<(String, LimiterConfig)> {
+        let path = request.uri().path();
+        let route = self
+            .routes
+            .iter()
+            .find(|r| r.method == request.method() && route_matches(&r.path, path));
+        if route.is_none() && !self.all {
+            return None;
+        }
+
+        let peer = request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|c| c.0);
+        let client = self.limit.identify(request.headers(), peer);
+        let clients = self.limit.clients.lock().unwrap();
+        Some(match route {
+            Some(route) => {
+                let name = format!("{} {}", self.name, route.name);
+                let config = clients.limits().for_route(&name, &client).clone();
+                (format!("{name} {client}"), config)
+            }
+            None => {
+                let config = clients.limits().for_client(&client).clone();
+                (format!("{} {client}", self.name), config)
+            }
+        })
+    }
+}
+
+/// The wait asked for with `Prefer: wait=<seconds>`, up to [`MAX_WAIT`].
+fn preferred_wait(headers: &HeaderMap) -> Option<Duration> {
+    headers
+        .get_all("prefer")
+        .iter()
+        .filter_map(|p| p.to_str().ok())
+        .flat_map(|p| p.split([',', ';']))
+        .find_map(|p| p.trim().strip_prefix("wait=")?.parse::<u64>().ok())
+        .map(|secs| Duration::from_secs(secs).min(MAX_WAIT))
+}
+
+/// A place in a bucket's queue, given up when dropped.
+struct Place<'a> {
+    limit: &'a RateLimit,
+    bucket: &'a str,
+    ticket: u64,
+}
+
+impl Drop for Place<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut clients) = self.limit.clients.lock() {
+            clients.dequeue(self.bucket, self.ticket);
+        }
+    }
+}
+
+impl RateLimit {
+    /// The client a request from `peer` comes from.
+    fn identify(&self, headers: &HeaderMap, peer: Option<SocketAddr>) -> String {
+        let ip = client_ip(headers, peer, self.trusted_proxies);
+        let clients = self.clients.lock().unwrap();
+        self.key.identify(headers, ip, clients.limits())
+    }
+
+    /// Draws from `bucket`, in the store if there is one. If the store
+    /// can't be reached the draw is made in memory instead, which limits
+    /// each instance on its own rather than not at all.
+    async fn try_acquire(&self, bucket: &str, config: &LimiterConfig) -> Quota {
+        if let Some(store) = &self.store {
+            match store.try_acquire(bucket, config).await {
+                Ok(quota) => return quota,
+                Err(e) => tracing::warn!("drawing from {bucket} in memory: {e}"),
+            }
+        }
+        let mut clients = self.clients.lock().unwrap();
+        clients.try_acquire_with(bucket, config)
+    }
+
+    async fn refill(&self, bucket: &str) {
+        if let Some(store) = &self.store {
+            if let Err(e) = store.refill(bucket).await {
+                tracing::warn!("couldn't refill {bucket}: {e}");
+            }
+        }
+        self.clients.lock().unwrap().refill(bucket);
+    }
+
+    /// Queues for `bucket` in memory until a draw succeeds or `deadline`.
+    /// Requests are served in the order they arrived. A request whose
+    /// client goes away is dropped along with this future, and leaves the
+    /// queue.
+    async fn queue(&self, bucket: &str, config: &LimiterConfig, deadline: Instant) -> Quota {
+        let (ticket, moved) = self.clients.lock().unwrap().enqueue(bucket, config);
+        let _place = Place {
+            limit: self,
+            bucket,
+            ticket,
+        };
+
+        loop {
+            // Listen before looking, so a move in between isn't missed
+            let mut notified = pin!(moved.notified());
+            notified.as_mut().enable();
+
+            let (quota, first) = self
+                .clients
+                .lock()
+                .unwrap()
+                .try_acquire_queued(bucket, config, ticket);
+            let now = Instant::now();
+            if quota.allowed || now >= deadline {
+                return quota;
+            }
+
+            // Only the first in line can tell when its turn comes
+            let until = match first {
+                true => (now + quota.retry_after).min(deadline),
+                false => deadline,
+            };
+            tokio::select! {
+                _ = notified => {}
+                _ = sleep_until(until) => {}
+            }
+        }
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimitService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimitService {
+            layer: self.clone(),
+            inner,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimitService<S> {
+    layer: RateLimitLayer,
+    inner: S,
+}
+
+impl<S> Service<Request> for RateLimitService<S>
+where
+    S: Service<Request, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        // The inner service was polled ready, so it is the one to call
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        let Some((bucket, config)) = self.layer.bucket(&request) else {
+            return Box::pin(inner.call(request));
+        };
+        let layer = self.layer.clone();
+        // Queues are kept in memory, so there are none with a store
+        let wait = preferred_wait(request.headers()).filter(|_| layer.limit.store.is_none());
+
+        Box::pin(async move {
+            let quota = match wait {
+                Some(wait) => {
+                    let deadline = Instant::now() + wait;
+                    layer.limit.queue(&bucket, &config, deadline).await
+                }
+                None => layer.limit.try_acquire(&bucket, &config).await,
+            };
+
+            let mut response = match quota.allowed {
+                true => inner.call(request).await?,
+                false => (StatusCode::TOO_MANY_REQUESTS, layer.message).into_response(),
+            };
+            quota.write_headers(response.headers_mut());
+            if let Some(wait) = wait {
+                let applied = format!("wait={}", wait.as_secs());
+                response.headers_mut().insert(
+                    "preference-applied",
+                    HeaderValue::from_str(&applied).unwrap(),
+                );
+            }
+            Ok(response)
+        })
+    }
+}
@@ -0,0 +1,490 @@
+//! The rate limiting algorithms a milk bucket can use. Every limiter is
+//! driven by the instants it is given rather than reading the time itself,
+//! so a [`MockClock`] can step through them deterministically.
+
+use std::{
+    collections::VecDeque,
+    fmt::Debug,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::http::{header::RETRY_AFTER, HeaderMap, HeaderValue};
+use serde::{Deserialize, Serialize};
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock that only moves when told to.
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    now: Arc<Mutex<Instant>>,
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self {
+            now: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+}
+
+impl MockClock {
+    pub fn advance(&self, by: Duration) {
+        *self.now.lock().unwrap() += by;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+}
+
+/// How far back a [`Timeline`] can place an instant. Older times are taken
+/// to be this old, which no limiter can tell apart from longer ago.
+const HORIZON: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Pairs an instant with a wall clock time, in microseconds since the Unix
+/// epoch, to carry limiter state between processes that share the clock.
+#[derive(Debug, Clone, Copy)]
+pub struct Timeline {
+    pub now: Instant,
+    wall: i64,
+}
+
+impl Timeline {
+    /// A timeline whose `now` is `wall`. `now` is set well ahead of the
+    /// process clock, so that times up to [`HORIZON`] ago have an instant.
+    pub fn new(wall: i64) -> Self {
+        Self {
+            now: Instant::now() + HORIZON,
+            wall,
+        }
+    }
+
+    pub fn wall(&self, at: Instant) -> i64 {
+        match at.checked_duration_since(self.now) {
+            Some(ahead) => self.wall + ahead.as_micros() as i64,
+            None => self.wall - self.now.duration_since(at).as_micros() as i64,
+        }
+    }
+
+    pub fn instant(&self, wall: i64) -> Instant {
+        let offset = Duration::from_micros(wall.abs_diff(self.wall));
+        match wall >= self.wall {
+            true => self.now + offset,
+            false => self.now - offset.min(HORIZON),
+        }
+    }
+}
+
+/// A limiter's state with its instants as wall clock times, see
+/// [`Timeline`]. The limits themselves come from the config.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "algorithm", rename_all = "kebab-case")]
+pub enum Snapshot {
+    TokenBucket { tokens: usize, next_refill: i64 },
+    FixedWindow { count: usize, window_end: i64 },
+    SlidingWindowLog { log: Vec<i64> },
+    Gcra { tat: i64 },
+}
+
+/// The outcome of drawing from a limiter, and the state it was left in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+    pub allowed: bool,
+    pub limit: usize,
+    pub remaining: usize,
+    /// Until the limiter is back to its full allowance.
+    pub reset: Duration,
+    /// Until the next withdrawal can succeed.
+    pub retry_after: Duration,
+}
+
+/// Whole seconds, rounded up so a client that waits this long will not be
+/// turned away again.
+fn seconds(duration: Duration) -> HeaderValue {
+    let mut secs = duration.as_secs();
+    if duration.subsec_nanos() > 0 {
+        secs += 1;
+    }
+    HeaderValue::from(secs)
+}
+
+impl Quota {
+    /// Adds the `RateLimit-*` and `Retry-After` headers.
+    pub fn write_headers(&self, headers: &mut HeaderMap) {
+        headers.insert("ratelimit-limit", HeaderValue::from(self.limit));
+        headers.insert("ratelimit-remaining", HeaderValue::from(self.remaining));
+        headers.insert("ratelimit-reset", seconds(self.reset));
+        headers.insert(RETRY_AFTER, seconds(self.retry_after));
+    }
+}
+
+pub trait MilkLimiter: Debug + Send {
+    /// Takes one unit at `now` if the limit allows it.
+    fn try_acquire(&mut self, now: Instant) -> Quota;
+
+    /// The limiter's state at `now` without taking anything. `allowed` says
+    /// whether [`MilkLimiter::try_acquire`] would succeed.
+    fn peek(&mut self, now: Instant) -> Quota;
+
+    /// Whether the limiter is back where a fresh one would start, so it can
+    /// be dropped without the client noticing.
+    fn is_idle(&self, now: Instant) -> bool;
+
+    fn snapshot(&self, timeline: &Timeline) -> Snapshot;
+
+    /// Takes up the state in `snapshot`, unless it is from another
+    /// algorithm.
+    fn restore(&mut self, snapshot: &Snapshot, timeline: &Timeline);
+}
+
+/// `refill` tokens are added every `interval`, on a fixed grid from when the
+/// bucket was made, up to `capacity`. The bucket starts full.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    capacity: usize,
+    refill: usize,
+    interval: Duration,
+    tokens: usize,
+    next_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(capacity: usize, refill: usize, interval: Duration, now: Instant) -> Self {
+        Self {
+            capacity,
+            refill,
+            interval,
+            tokens: capacity,
+            next_refill: now + interval,
+        }
+    }
+
+    fn update(&mut self, now: Instant) {
+        if now < self.next_refill {
+            return;
+        }
+        let periods =
+            now.duration_since(self.next_refill).as_nanos() / self.interval.as_nanos() + 1;
+        let added = usize::try_from(periods)
+            .unwrap_or(usize::MAX)
+            .saturating_mul(self.refill);
+        self.tokens = self.tokens.saturating_add(added).min(self.capacity);
+        self.next_refill += self.interval * periods as u32;
+    }
+
+    fn quota(&self, allowed: bool, now: Instant) -> Quota {
+        let until_refill = self.next_refill.duration_since(now);
+        let reset = match self.capacity - self.tokens {
+            0 => Duration::ZERO,
+            missing => {
+                let refills = missing.div_ceil(self.refill) as u32;
+                until_refill + self.interval * (refills - 1)
+            }
+        };
+        Quota {
+            allowed,
+            limit: self.capacity,
+            remaining: self.tokens,
+            reset,
+            retry_after: match self.tokens {
+                0 => until_refill,
+                _ => Duration::ZERO,
+            },
+        }
+    }
+}
+
+impl MilkLimiter for TokenBucket {
+    fn try_acquire(&mut self, now: Instant) -> Quota {
+        self.update(now);
+        let allowed = self.tokens > 0;
+        if allowed {
+            self.tokens -= 1;
+        }
+        self.quota(allowed, now)
+    }
+
+    fn peek(&mut self, now: Instant) -> Quota {
+        self.update(now);
+        self.quota(self.tokens > 0, now)
+    }
+
+    fn is_idle(&self, now: Instant) -> bool {
+        let mut bucket = self.clone();
+        bucket.update(now);
+        bucket.tokens == bucket.capacity
+    }
+
+    fn snapshot(&self, timeline: &Timeline) -> Snapshot {
+        Snapshot::TokenBucket {
+            tokens: self.tokens,
+            next_refill: timeline.wall(self.next_refill),
+        }
+    }
+
+    fn restore(&mut self, snapshot: &Snapshot, timeline: &Timeline) {
+        if let Snapshot::TokenBucket {
+            tokens,
+            next_refill,
+        } = *snapshot
+        {
+            self.tokens = tokens.min(self.capacity);
+            self.next_refill = timeline.instant(next_refill);
+        }
+    }
+}
+
+/// At most `limit` withdrawals per `window`, with windows back to back from
+/// when the limiter was made.
+#[derive(Debug, Clone)]
+pub struct FixedWindow {
+    limit: usize,
+    window: Duration,
+    count: usize,
+    window_end: Instant,
+}
+
+impl FixedWindow {
+    pub fn new(limit: usize, window: Duration, now: Instant) -> Self {
+        Self {
+            limit,
+            window,
+            count: 0,
+            window_end: now + window,
+        }
+    }
+
+    fn update(&mut self, now: Instant) {
+        if now >= self.window_end {
+            let windows =
+                now.duration_since(self.window_end).as_nanos() / self.window.as_nanos() + 1;
+            self.window_end += self.window * windows as u32;
+            self.count = 0;
+        }
+    }
+
+    fn quota(&self, allowed: bool, now: Instant) -> Quota {
+        let remaining = self.limit - self.count;
+        let until_end = self.window_end.duration_since(now);
+        Quota {
+            allowed,
+            limit: self.limit,
+            remaining,
+            reset: match self.count {
+                0 => Duration::ZERO,
+                _ => until_end,
+            },
+            retry_after: match remaining {
+                0 => until_end,
+                _ => Duration::ZERO,
+            },
+        }
+    }
+}
+
+impl MilkLimiter for FixedWindow {
+    fn try_acquire(&mut self, now: Instant) -> Quota {
+        self.update(now);
+        let allowed = self.count < self.limit;
+        if allowed {
+            self.count += 1;
+        }
+        self.quota(allowed, now)
+    }
+
+    fn peek(&mut self, now: Instant) -> Quota {
+        self.update(now);
+        self.quota(self.count < self.limit, now)
+    }
+
+    fn is_idle(&self, now: Instant) -> bool {
+        self.count == 0 || now >= self.window_end
+    }
+
+    fn snapshot(&self, timeline: &Timeline) -> Snapshot {
+        Snapshot::FixedWindow {
+            count: self.count,
+            window_end: timeline.wall(self.window_end),
+        }
+    }
+
+    fn restore(&mut self, snapshot: &Snapshot, timeline: &Timeline) {
+        if let Snapshot::FixedWindow { count, window_end } = *snapshot {
+            self.count = count.min(self.limit);
+            self.window_end = timeline.instant(window_end);
+        }
+    }
+}
+
+/// At most `limit` withdrawals in any `window` long stretch, remembering
+/// when each one happened.
+#[derive(Debug, Clone)]
+pub struct SlidingWindowLog {
+    limit: usize,
+    window: Duration,
+    log: VecDeque<Instant>,
+}
+
+impl SlidingWindowLog {
+    pub fn new(limit: usize, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            log: VecDeque::with_capacity(limit),
+        }
+    }
+
+    fn update(&mut self, now: Instant) {
+        while self
+            .log
+            .front()
+            .is_some_and(|&at| now.duration_since(at) >= self.window)
+        {
+            self.log.pop_front();
+        }
+    }
+
+    fn quota(&self, allowed: bool, now: Instant) -> Quota {
+        let expires = |at: Option<&Instant>| {
+            at.map_or(Duration::ZERO, |&at| (at + self.window).duration_since(now))
+        };
+        let remaining = self.limit - self.log.len();
+        Quota {
+            allowed,
+            limit: self.limit,
+            remaining,
+            reset: expires(self.log.back()),
+            retry_after: match remaining {
+                0 => expires(self.log.front()),
+                _ => Duration::ZERO,
+            },
+        }
+    }
+}
+
+impl MilkLimiter for SlidingWindowLog {
+    fn try_acquire(&mut self, now: Instant) -> Quota {
+        self.update(now);
+        let allowed = self.log.len() < self.limit;
+        if allowed {
+            self.log.push_back(now);
+        }
+        self.quota(allowed, now)
+    }
+
+    fn peek(&mut self, now: Instant) -> Quota {
+        self.update(now);
+        self.quota(self.log.len() < self.limit, now)
+    }
+
+    fn is_idle(&self, now: Instant) -> bool {
+        self.log
+            .back()
+            .is_none_or(|&at| now.duration_since(at) >= self.window)
+    }
+
+    fn snapshot(&self, timeline: &Timeline) -> Snapshot {
+        Snapshot::SlidingWindowLog {
+            log: self.log.iter().map(|&at| timeline.wall(at)).collect(),
+        }
+    }
+
+    fn restore(&mut self, snapshot: &Snapshot, timeline: &Timeline) {
+        if let Snapshot::SlidingWindowLog { log } = snapshot {
+            let skip = log.len().saturating_sub(self.limit);
+            self.log = log[skip..].iter().map(|&at| timeline.instant(at)).collect();
+        }
+    }
+}
+
+/// The generic cell rate algorithm: one withdrawal per `interval` on
+/// average, with bursts of up to `burst`. Only the theoretical arrival
+/// time is kept.
+#[derive(Debug, Clone)]
+pub struct Gcra {
+    burst: usize,
+    interval: Duration,
+    tat: Instant,
+}
+
+impl Gcra {
+    pub fn new(burst: usize, interval: Duration, now: Instant) -> Self {
+        Self {
+            burst,
+            interval,
+            tat: now,
+        }
+    }
+
+    /// How far ahead of `now` the arrival time may run.
+    fn tolerance(&self) -> Duration {
+        self.interval * self.burst as u32
+    }
+
+    /// The arrival time after one more withdrawal at `now`, if that is
+    /// within the tolerance.
+    fn next_tat(&self, now: Instant) -> Option<Instant> {
+        let tat = self.tat.max(now) + self.interval;
+        (tat.duration_since(now) <= self.tolerance()).then_some(tat)
+    }
+
+    fn quota(&self, allowed: bool, now: Instant) -> Quota {
+        let ahead = self.tat.saturating_duration_since(now);
+        let headroom = self.tolerance().saturating_sub(ahead);
+        let remaining = (headroom.as_nanos() / self.interval.as_nanos()) as usize;
+        Quota {
+            allowed,
+            limit: self.burst,
+            remaining,
+            reset: ahead,
+            retry_after: match remaining {
+                0 => self.interval - headroom,
+                _ => Duration::ZERO,
+            },
+        }
+    }
+}
+
+impl MilkLimiter for Gcra {
+    fn try_acquire(&mut self, now: Instant) -> Quota {
+        let tat = self.next_tat(now);
+        if let Some(tat) = tat {
+            self.tat = tat;
+        }
+        self.quota(tat.is_some(), now)
+    }
+
+    fn peek(&mut self, now: Instant) -> Quota {
+        self.quota(self.next_tat(now).is_some(), now)
+    }
+
+    fn is_idle(&self, now: Instant) -> bool {
+        self.tat <= now
+    }
+
+    fn snapshot(&self, timeline: &Timeline) -> Snapshot {
+        Snapshot::Gcra {
+            tat: timeline.wall(self.tat),
+        }
+    }
+
+    fn restore(&mut self, snapshot: &Snapshot, timeline: &Timeline) {
+        if let Snapshot::Gcra { tat } = *snapshot {
+            self.tat = timeline.instant(tat);
+        }
+    }
+}
@@ -0,0 +1,110 @@
+//! Milk buckets kept in Postgres, so that every instance behind the load
+//! balancer draws from one quota and a restart doesn't refill them. A draw
+//! holds its bucket's row lock until it has written the bucket back, and
+//! is timed by the database's clock rather than the instance's.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use sqlx::PgPool;
+
+use super::{
+    clients::SWEEP_EVERY,
+    config::LimiterConfig,
+    limiter::{Quota, Snapshot, Timeline},
+};
+
+#[derive(Clone)]
+pub struct PgBuckets {
+    pool: PgPool,
+    swept: Arc<Mutex<Instant>>,
+}
+
+impl PgBuckets {
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            pool,
+            swept: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    /// Draws from `bucket`, making it with `config` if it isn't stored.
+    /// Whatever state is stored, the limits are always `config`'s.
+    pub async fn try_acquire(
+        &self,
+        bucket: &str,
+        config: &LimiterConfig,
+    ) -> Result<Quota, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "INSERT INTO milk_buckets (bucket) VALUES ($1) ON CONFLICT (bucket) DO NOTHING",
+        )
+        .bind(bucket)
+        .execute(&mut *tx)
+        .await?;
+        let state: Option<String> =
+            sqlx::query_scalar("SELECT state::text FROM milk_buckets WHERE bucket = $1 FOR UPDATE")
+                .bind(bucket)
+                .fetch_one(&mut *tx)
+                .await?;
+        // Only read the clock once the row is ours, so that a draw is never
+        // timed before the one it waited for
+        let wall: DateTime<Utc> = sqlx::query_scalar("SELECT clock_timestamp()")
+            .fetch_one(&mut *tx)
+            .await?;
+
+        let timeline = Timeline::new(wall.timestamp_micros());
+        let mut limiter = config.build(timeline.now);
+        if let Some(snapshot) = state.and_then(|s| serde_json::from_str::<Snapshot>(&s).ok()) {
+            limiter.restore(&snapshot, &timeline);
+        }
+        let quota = limiter.try_acquire(timeline.now);
+
+        let idle_at = TimeDelta::from_std(quota.reset)
+            .ok()
+            .and_then(|reset| wall.checked_add_signed(reset))
+            .unwrap_or(DateTime::<Utc>::MAX_UTC);
+        let snapshot = serde_json::to_string(&limiter.snapshot(&timeline)).unwrap();
+        sqlx::query("UPDATE milk_buckets SET state = $2::jsonb, idle_at = $3 WHERE bucket = $1")
+            .bind(bucket)
+            .bind(snapshot)
+            .bind(idle_at)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        self.sweep().await;
+        Ok(quota)
+    }
+
+    /// Gives `bucket` its full allowance again.
+    pub async fn refill(&self, bucket: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM milk_buckets WHERE bucket = $1")
+            .bind(bucket)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Drops the buckets that are back to a fresh state, at most once per
+    /// [`SWEEP_EVERY`] from each instance.
+    async fn sweep(&self) {
+        {
+            let mut swept = self.swept.lock().unwrap();
+            if swept.elapsed() < SWEEP_EVERY {
+                return;
+            }
+            *swept = Instant::now();
+        }
+
+        if let Err(e) = sqlx::query("DELETE FROM milk_buckets WHERE idle_at <= now()")
+            .execute(&self.pool)
+            .await
+        {
+            tracing::warn!("couldn't sweep milk buckets: {e}");
+        }
+    }
+}
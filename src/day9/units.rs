@@ -0,0 +1,105 @@
+//! Unit conversion driven by the catalogue in `assets/day9_units.toml`.
+//! Conversions go through the base unit of the quantity both units measure.
+
+use std::{collections::BTreeMap, fmt, sync::OnceLock};
+
+use serde::{Deserialize, Serialize};
+
+const CATALOGUE: &str = include_str!("../../assets/day9_units.toml");
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Unit {
+    pub quantity: String,
+    pub symbol: String,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    pub factor: f64,
+    #[serde(default)]
+    pub offset: f64,
+}
+
+impl Unit {
+    fn to_base(&self, value: f64) -> f64 {
+        (value + self.offset) * self.factor
+    }
+
+    fn in_unit(&self, value: f64) -> f64 {
+        value / self.factor - self.offset
+    }
+}
+
+/// The units there are, by id, and the base unit of each quantity.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Catalogue {
+    pub quantities: BTreeMap<String, String>,
+    pub units: BTreeMap<String, Unit>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConversionError {
+    UnknownUnit(String),
+    Incompatible {
+        from: String,
+        from_quantity: String,
+        to: String,
+        to_quantity: String,
+    },
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConversionError::UnknownUnit(unit) => write!(f, "Unknown unit `{unit}`"),
+            ConversionError::Incompatible {
+                from,
+                from_quantity,
+                to,
+                to_quantity,
+            } => write!(
+                f,
+                "Can't convert `{from}` ({from_quantity}) to `{to}` ({to_quantity})"
+            ),
+        }
+    }
+}
+
+/// Unit names are matched ignoring case, with spaces and dashes read as
+/// underscores, so "US gallon" finds `us_gallon`.
+fn normalize(name: &str) -> String {
+    name.trim().to_lowercase().replace([' ', '-'], "_")
+}
+
+impl Catalogue {
+    /// The catalogue shipped with the service.
+    pub fn builtin() -> &'static Catalogue {
+        static BUILTIN: OnceLock<Catalogue> = OnceLock::new();
+        BUILTIN.get_or_init(|| toml::from_str(CATALOGUE).expect("invalid day9 unit catalogue"))
+    }
+
+    /// The unit `name` refers to, by id or alias, with its id.
+    pub fn find(&self, name: &str) -> Option<(&str, &Unit)> {
+        let name = normalize(name);
+        self.units
+            .iter()
+            .find(|(id, unit)| **id == name || unit.aliases.iter().any(|a| normalize(a) == name))
+            .map(|(id, unit)| (id.as_str(), unit))
+    }
+
+    pub fn convert(&self, value: f64, from: &str, to: &str) -> Result<f64, ConversionError> {
+        let unknown = |name: &str| ConversionError::UnknownUnit(name.to_string());
+        let (from_id, from_unit) = self.find(from).ok_or_else(|| unknown(from))?;
+        let (to_id, to_unit) = self.find(to).ok_or_else(|| unknown(to))?;
+        if from_unit.quantity != to_unit.quantity {
+            return Err(ConversionError::Incompatible {
+                from: from_id.to_string(),
+                from_quantity: from_unit.quantity.clone(),
+                to: to_id.to_string(),
+                to_quantity: to_unit.quantity.clone(),
+            });
+        }
+
+        Ok(to_unit.in_unit(from_unit.to_base(value)))
+    }
+}
@@ -1,19 +1,20 @@
-use std::convert::Infallible;
+use std::net::SocketAddr;
 
 use axum::{
-    body::{to_bytes, Body, Bytes},
     http::{self, HeaderMap, StatusCode},
-    middleware::Next,
-    response::{IntoResponse, Response},
-    routing::{get, post},
+    response::IntoResponse,
+    routing::get,
     Extension, Router,
 };
-use hyper::Request;
+use shuttle_runtime::{CustomError, Error};
 use shuttlings_cch24::{
     day12::day_12_routes, day16::day_16_routes, day19::day_19_routes, day23::day_23_routes, day5::*,
 };
-use shuttlings_cch24::{day2::*, day9::day_9_routes};
-use tower_http::{body::Full, services::ServeDir, trace::TraceLayer};
+use shuttlings_cch24::{
+    day2::*,
+    day9::{day_9_routes, layer::RateLimit},
+};
+use tower_http::{services::ServeDir, trace::TraceLayer};
 
 async fn hello_world() -> &'static str {
     "Hello, bird!"
@@ -32,25 +33,46 @@ async fn seek_negative_one() -> impl IntoResponse {
     (StatusCode::FOUND, headers)
 }
 
+/// Serves the router with each connection's peer address, which the day 9
+/// rate limiter tells clients apart by.
+struct ConnectInfoService(Router);
+
+#[shuttle_runtime::async_trait]
+impl shuttle_runtime::Service for ConnectInfoService {
+    async fn bind(self, addr: SocketAddr) -> Result<(), Error> {
+        let listener = shuttle_runtime::tokio::net::TcpListener::bind(addr)
+            .await
+            .map_err(CustomError::new)?;
+        axum::serve(
+            listener,
+            self.0.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+        .map_err(CustomError::new)?;
+
+        Ok(())
+    }
+}
+
 #[shuttle_runtime::main]
-async fn main(#[shuttle_shared_db::Postgres] pool: sqlx::PgPool) -> shuttle_axum::ShuttleAxum {
+async fn main(
+    #[shuttle_shared_db::Postgres] pool: sqlx::PgPool,
+) -> Result<ConnectInfoService, Error> {
     sqlx::migrate!()
         .run(&pool)
         .await
         .expect("Failed to run migrations");
+    let limits = RateLimit::from_env(&pool);
     let router = Router::new()
         .route("/", get(hello_world))
-        .route("/2/dest", get(dest_2))
-        .route("/2/key", get(key_2))
-        .route("/2/v6/dest", get(dest_2_v6))
-        .route("/2/v6/key", get(key_2_v6))
+        .nest("/2", day_2_routes())
         .nest("/5", day_05_routes())
         .route("/-1/seek", get(seek_negative_one))
-        .nest("/9", day_9_routes())
+        .nest("/9", day_9_routes(limits.clone()))
         .nest("/12", day_12_routes())
         .nest("/16", day_16_routes())
         .nest("/23", day_23_routes())
-        .nest("/19", day_19_routes(pool.clone()))
+        .nest("/19", day_19_routes(pool.clone(), &limits))
         .nest_service("/assets", ServeDir::new("assets"))
         .layer(Extension(pool))
         // .layer(axum::middleware::from_fn(log_response_middleware))
@@ -77,7 +99,7 @@ async fn main(#[shuttle_shared_db::Postgres] pool: sqlx::PgPool) -> shuttle_axum
                 ),
         );
 
-    Ok(router.into())
+    Ok(ConnectInfoService(router))
 }
 
 // async fn log_response_middleware(req: Request<axum::body::Body>, next: Next) -> impl IntoResponse {
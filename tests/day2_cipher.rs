@@ -0,0 +1,75 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use proptest::prelude::*;
+use shuttlings_cch24::day2::{derive_key_v4, derive_key_v6, encrypt_v4, encrypt_v6, Op};
+
+fn any_op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        Just(Op::Add),
+        Just(Op::Sub),
+        Just(Op::Xor),
+        (0u32..256).prop_map(Op::Rotl),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn v4_key_round_trips(from in any::<Ipv4Addr>(), to in any::<Ipv4Addr>()) {
+        prop_assert_eq!(encrypt_v4(from, derive_key_v4(from, to)), to);
+    }
+
+    #[test]
+    fn v6_key_round_trips(from in any::<Ipv6Addr>(), to in any::<Ipv6Addr>()) {
+        prop_assert_eq!(encrypt_v6(from, derive_key_v6(from, to)), to);
+    }
+
+    #[test]
+    fn v4_derives_the_encrypting_key(from in any::<Ipv4Addr>(), key in any::<Ipv4Addr>()) {
+        prop_assert_eq!(derive_key_v4(from, encrypt_v4(from, key)), key);
+    }
+
+    #[test]
+    fn v6_derives_the_encrypting_key(from in any::<Ipv6Addr>(), key in any::<Ipv6Addr>()) {
+        prop_assert_eq!(derive_key_v6(from, encrypt_v6(from, key)), key);
+    }
+
+    #[test]
+    fn v4_op_key_round_trips(op in any_op(), from in any::<Ipv4Addr>(), to in any::<Ipv4Addr>()) {
+        prop_assert_eq!(op.encrypt_v4(from, op.derive_key_v4(from, to)), to);
+    }
+
+    #[test]
+    fn v4_chain_matches_hop_by_hop(op in any_op(), from in any::<Ipv4Addr>(), keys in prop::collection::vec(any::<Ipv4Addr>(), 0..8)) {
+        let (composite_op, composite_key) = op.chain_v4(&keys);
+        let to = keys.iter().fold(from, |addr, &key| op.encrypt_v4(addr, key));
+        prop_assert_eq!(composite_op.encrypt_v4(from, composite_key), to);
+    }
+
+    #[test]
+    fn v6_chain_matches_hop_by_hop(op in any_op(), from in any::<Ipv6Addr>(), keys in prop::collection::vec(any::<Ipv6Addr>(), 0..8)) {
+        let (composite_op, composite_key) = op.chain_v6(&keys);
+        let to = keys.iter().fold(from, |addr, &key| op.encrypt_v6(addr, key));
+        prop_assert_eq!(composite_op.encrypt_v6(from, composite_key), to);
+    }
+
+    #[test]
+    fn v6_op_key_round_trips(op in any_op(), from in any::<Ipv6Addr>(), to in any::<Ipv6Addr>()) {
+        prop_assert_eq!(op.encrypt_v6(from, op.derive_key_v6(from, to)), to);
+    }
+}
+
+#[test]
+fn v4_wraps_per_octet() {
+    let from = Ipv4Addr::new(10, 0, 0, 250);
+    let key = Ipv4Addr::new(1, 2, 3, 10);
+
+    assert_eq!(encrypt_v4(from, key), Ipv4Addr::new(11, 2, 3, 4));
+}
+
+#[test]
+fn ops_parse_from_query_values() {
+    assert_eq!("add".parse::<Op>(), Ok(Op::Add));
+    assert_eq!("rotl:12".parse::<Op>(), Ok(Op::Rotl(12)));
+    assert!("rotl:".parse::<Op>().is_err());
+    assert!("mul".parse::<Op>().is_err());
+}
@@ -0,0 +1,521 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use shuttlings_cch24::day2::{
+    pcap::{rewrite, Keys, PcapError},
+    Op,
+};
+
+const V4_KEY: Ipv4Addr = Ipv4Addr::new(1, 2, 3, 4);
+const V6_KEY: Ipv6Addr = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0xff);
+
+const TCP: u8 = 6;
+const UDP: u8 = 17;
+const ICMP: u8 = 1;
+const ICMPV6: u8 = 58;
+
+fn keys() -> Keys {
+    Keys {
+        v4: Some((Op::Add, V4_KEY)),
+        v6: Some((Op::Xor, V6_KEY)),
+    }
+}
+
+fn enc4(addr: Ipv4Addr) -> Ipv4Addr {
+    Op::Add.encrypt_v4(addr, V4_KEY)
+}
+
+fn enc6(addr: Ipv6Addr) -> Ipv6Addr {
+    Op::Xor.encrypt_v6(addr, V6_KEY)
+}
+
+/// The ones' complement checksum of `parts` one after the other.
+fn checksum(parts: &[&[u8]]) -> u16 {
+    let data = parts.concat();
+    let mut sum: u64 = data
+        .chunks(2)
+        .map(|c| u16::from_be_bytes([c[0], c.get(1).copied().unwrap_or(0)]) as u64)
+        .sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// A transport segment whose checksum is filled in once the addresses of
+/// the packet carrying it are known.
+#[derive(Clone)]
+struct Segment {
+    proto: u8,
+    bytes: Vec<u8>,
+    checksummed: bool,
+}
+
+fn udp(payload: &[u8]) -> Segment {
+    let len = (8 + payload.len()) as u16;
+    let mut bytes = [&[0x04, 0xd2, 0x00, 0x35][..], &len.to_be_bytes(), &[0, 0]].concat();
+    bytes.extend(payload);
+    Segment {
+        proto: UDP,
+        bytes,
+        checksummed: true,
+    }
+}
+
+fn tcp(payload: &[u8]) -> Segment {
+    let mut bytes = vec![
+        0x04, 0xd2, 0x00, 0x50, 0, 0, 0, 1, 0, 0, 0, 0, 0x50, 0x18, 0xff, 0xff, 0, 0, 0, 0,
+    ];
+    bytes.extend(payload);
+    Segment {
+        proto: TCP,
+        bytes,
+        checksummed: true,
+    }
+}
+
+fn icmp(kind: u8, rest: [u8; 4], body: &[u8]) -> Segment {
+    Segment {
+        proto: ICMP,
+        bytes: [&[kind, 0, 0, 0][..], &rest, body].concat(),
+        checksummed: true,
+    }
+}
+
+fn icmpv6(kind: u8, body: &[u8]) -> Segment {
+    Segment {
+        proto: ICMPV6,
+        bytes: [&[kind, 0, 0, 0][..], &[0; 4], body].concat(),
+        checksummed: true,
+    }
+}
+
+impl Segment {
+    fn with_bytes(&self, pseudo: &[u8]) -> Vec<u8> {
+        let mut bytes = self.bytes.clone();
+        if !self.checksummed {
+            return bytes;
+        }
+        let (at, pseudo) = match self.proto {
+            TCP => (16, pseudo),
+            UDP => (6, pseudo),
+            ICMP => (2, &[][..]),
+            _ => (2, pseudo),
+        };
+        let mut sum = checksum(&[pseudo, &bytes]);
+        if self.proto == UDP && sum == 0 {
+            sum = 0xffff;
+        }
+        bytes[at..at + 2].copy_from_slice(&sum.to_be_bytes());
+        bytes
+    }
+}
+
+fn ipv4(src: Ipv4Addr, dst: Ipv4Addr, segment: &Segment) -> Vec<u8> {
+    let len = (20 + segment.bytes.len()) as u16;
+    let mut packet = [
+        &[0x45, 0][..],
+        &len.to_be_bytes(),
+        &[0, 0, 0, 0, 64, segment.proto, 0, 0],
+        &src.octets(),
+        &dst.octets(),
+    ]
+    .concat();
+    let sum = checksum(&[&packet]);
+    packet[10..12].copy_from_slice(&sum.to_be_bytes());
+
+    let upper_len = segment.bytes.len() as u16;
+    let pseudo = [
+        &src.octets()[..],
+        &dst.octets(),
+        &[0, segment.proto],
+        &upper_len.to_be_bytes(),
+    ]
+    .concat();
+    packet.extend(segment.with_bytes(&pseudo));
+    packet
+}
+
+/// An IPv6 packet with the extension headers `extensions` (hop-by-hop,
+/// routing, fragment or destination options) in front of `segment`.
+fn ipv6(src: Ipv6Addr, dst: Ipv6Addr, extensions: &[u8], segment: &Segment) -> Vec<u8> {
+    let nexts = extensions.iter().skip(1).chain([&segment.proto]);
+    let headers = extensions
+        .iter()
+        .zip(nexts)
+        .flat_map(|(&kind, &next)| match kind {
+            44 => vec![next, 0, 0, 0, 0, 0, 0, 1],
+            43 => [&[next, 2, 0, 1, 0, 0, 0, 0][..], &[0x20; 16]].concat(),
+            _ => vec![next, 0, 1, 4, 0, 0, 0, 0],
+        })
+        .collect::<Vec<u8>>();
+
+    let len = (headers.len() + segment.bytes.len()) as u16;
+    let first = extensions.first().unwrap_or(&segment.proto);
+    let mut packet = [
+        &[0x60, 0, 0, 0][..],
+        &len.to_be_bytes(),
+        &[*first, 64],
+        &src.octets(),
+        &dst.octets(),
+    ]
+    .concat();
+    packet.extend(&headers);
+
+    let upper_len = segment.bytes.len() as u32;
+    let pseudo = [
+        &src.octets()[..],
+        &dst.octets(),
+        &upper_len.to_be_bytes(),
+        &[0, 0, 0, segment.proto],
+    ]
+    .concat();
+    packet.extend(segment.with_bytes(&pseudo));
+    packet
+}
+
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+fn ethernet(tags: &[u16], ethertype: u16, packet: &[u8]) -> Vec<u8> {
+    let mut frame = vec![2, 0, 0, 0, 0, 1, 2, 0, 0, 0, 0, 2];
+    for tag in tags {
+        frame.extend(tag.to_be_bytes());
+        frame.extend([0, 7]);
+    }
+    frame.extend(ethertype.to_be_bytes());
+    frame.extend(packet);
+    frame
+}
+
+/// `packet` framed for `link`, with `v6` saying which family it is.
+fn frame(link: u32, v6: bool, packet: &[u8]) -> Vec<u8> {
+    let ethertype: u16 = if v6 { 0x86dd } else { 0x0800 };
+    let header = match link {
+        LINKTYPE_ETHERNET => return ethernet(&[], ethertype, packet),
+        LINKTYPE_NULL => (if v6 { 30u32 } else { 2 }).to_le_bytes().to_vec(),
+        LINKTYPE_LINUX_SLL => [
+            &[0, 0, 0, 1, 0, 6, 2, 0, 0, 0, 0, 1, 0, 0][..],
+            &ethertype.to_be_bytes(),
+        ]
+        .concat(),
+        LINKTYPE_LINUX_SLL2 => [&ethertype.to_be_bytes()[..], &[0; 18]].concat(),
+        _ => vec![],
+    };
+    [header, packet.to_vec()].concat()
+}
+
+/// A little endian capture of `frames`, each given as what was captured and
+/// how long the frame really was.
+fn pcap_with(link: u32, frames: &[(Vec<u8>, usize)]) -> Vec<u8> {
+    let mut capture = [
+        &0xa1b2c3d4u32.to_le_bytes()[..],
+        &2u16.to_le_bytes(),
+        &4u16.to_le_bytes(),
+        &[0; 8],
+        &65535u32.to_le_bytes(),
+        &link.to_le_bytes(),
+    ]
+    .concat();
+    for (frame, len) in frames {
+        capture.extend([0; 8]);
+        capture.extend((frame.len() as u32).to_le_bytes());
+        capture.extend((*len as u32).to_le_bytes());
+        capture.extend(frame);
+    }
+    capture
+}
+
+fn pcap(link: u32, frames: &[Vec<u8>]) -> Vec<u8> {
+    let frames = frames
+        .iter()
+        .map(|f| (f.clone(), f.len()))
+        .collect::<Vec<_>>();
+    pcap_with(link, &frames)
+}
+
+fn host4(n: u8) -> Ipv4Addr {
+    Ipv4Addr::new(192, 168, 0, n)
+}
+
+fn host6(n: u16) -> Ipv6Addr {
+    Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, n)
+}
+
+#[test]
+fn every_link_type_is_rewritten() {
+    let v4 = |src, dst| ipv4(src, dst, &udp(b"milk"));
+    let v6 = |src, dst| ipv6(src, dst, &[], &tcp(b"cookies"));
+    let links = [
+        (LINKTYPE_NULL, true, true),
+        (LINKTYPE_ETHERNET, true, true),
+        (LINKTYPE_RAW, true, true),
+        (LINKTYPE_LINUX_SLL, true, true),
+        (LINKTYPE_IPV4, true, false),
+        (LINKTYPE_IPV6, false, true),
+        (LINKTYPE_LINUX_SLL2, true, true),
+    ];
+
+    for (link, has_v4, has_v6) in links {
+        let (mut before, mut after) = (vec![], vec![]);
+        if has_v4 {
+            before.push(frame(link, false, &v4(host4(1), host4(2))));
+            after.push(frame(link, false, &v4(enc4(host4(1)), enc4(host4(2)))));
+        }
+        if has_v6 {
+            before.push(frame(link, true, &v6(host6(1), host6(2))));
+            after.push(frame(link, true, &v6(enc6(host6(1)), enc6(host6(2)))));
+        }
+
+        assert_eq!(
+            rewrite(&pcap(link, &before), &keys()),
+            Ok(pcap(link, &after)),
+            "link type {link}"
+        );
+    }
+}
+
+#[test]
+fn big_endian_captures_are_read() {
+    let packet = |src, dst| frame(LINKTYPE_NULL, false, &ipv4(src, dst, &udp(b"milk")));
+    // A big endian host writes the family, like the rest, in its own order
+    let to_be = |capture: Vec<u8>| {
+        let mut capture = capture;
+        for range in [0..4, 16..20, 20..24, 24..28, 32..36, 36..40, 40..44] {
+            capture[range].reverse();
+        }
+        capture[4..6].reverse();
+        capture[6..8].reverse();
+        capture
+    };
+
+    let before = to_be(pcap(LINKTYPE_NULL, &[packet(host4(1), host4(2))]));
+    let after = to_be(pcap(
+        LINKTYPE_NULL,
+        &[packet(enc4(host4(1)), enc4(host4(2)))],
+    ));
+    assert_eq!(rewrite(&before, &keys()), Ok(after));
+}
+
+#[test]
+fn vlan_tagged_frames_are_rewritten() {
+    for tags in [&[0x8100][..], &[0x88a8, 0x8100]] {
+        let packet = |src, dst| ethernet(tags, 0x0800, &ipv4(src, dst, &tcp(b"milk")));
+
+        assert_eq!(
+            rewrite(
+                &pcap(LINKTYPE_ETHERNET, &[packet(host4(1), host4(2))]),
+                &keys()
+            ),
+            Ok(pcap(
+                LINKTYPE_ETHERNET,
+                &[packet(enc4(host4(1)), enc4(host4(2)))]
+            )),
+            "{tags:x?}"
+        );
+    }
+}
+
+#[test]
+fn ipv6_extension_headers_are_skipped() {
+    for extensions in [&[0][..], &[0, 43, 60], &[44], &[60, 44]] {
+        let packet = |src, dst| ipv6(src, dst, extensions, &udp(b"milk"));
+
+        assert_eq!(
+            rewrite(&pcap(LINKTYPE_IPV6, &[packet(host6(1), host6(2))]), &keys()),
+            Ok(pcap(
+                LINKTYPE_IPV6,
+                &[packet(enc6(host6(1)), enc6(host6(2)))]
+            )),
+            "{extensions:?}"
+        );
+    }
+}
+
+#[test]
+fn zero_udp_checksums_stay_zero() {
+    let segment = Segment {
+        checksummed: false,
+        ..udp(b"milk")
+    };
+    let packet = |src, dst| ipv4(src, dst, &segment);
+    let after = packet(enc4(host4(1)), enc4(host4(2)));
+    assert_eq!(after[26..28], [0, 0]);
+
+    assert_eq!(
+        rewrite(&pcap(LINKTYPE_IPV4, &[packet(host4(1), host4(2))]), &keys()),
+        Ok(pcap(LINKTYPE_IPV4, &[after]))
+    );
+}
+
+#[test]
+fn packets_cut_at_the_snap_length_are_fixed_up() {
+    let packet = |src, dst| ipv4(src, dst, &tcp(&[7; 200]));
+    let (before, after) = (
+        packet(host4(1), host4(2)),
+        packet(enc4(host4(1)), enc4(host4(2))),
+    );
+    let len = before.len();
+
+    // The TCP checksum made it into the capture, the data it covers didn't
+    assert_eq!(
+        rewrite(
+            &pcap_with(LINKTYPE_RAW, &[(before[..48].to_vec(), len)]),
+            &keys()
+        ),
+        Ok(pcap_with(LINKTYPE_RAW, &[(after[..48].to_vec(), len)]))
+    );
+}
+
+#[test]
+fn truncated_records_are_rejected() {
+    let capture = pcap(LINKTYPE_RAW, &[ipv4(host4(1), host4(2), &udp(b"milk"))]);
+
+    for cut in [1, capture.len() - 24 - 8, 20] {
+        assert_eq!(
+            rewrite(&capture[..capture.len() - cut], &keys()),
+            Err(PcapError::Truncated),
+            "{cut} bytes short"
+        );
+    }
+}
+
+#[test]
+fn icmp_errors_rewrite_the_quoted_packet() {
+    // A router tells a host its datagram couldn't be delivered, quoting the
+    // IP header and first 8 bytes of it
+    let quoted = |src, dst| ipv4(src, dst, &udp(b"milk and cookies"))[..28].to_vec();
+    let error = |router, host, dst| ipv4(router, host, &icmp(3, [0; 4], &quoted(host, dst)));
+    // And another that it should use a different gateway
+    let redirect = |router, host, gateway: Ipv4Addr, dst| {
+        ipv4(router, host, &icmp(5, gateway.octets(), &quoted(host, dst)))
+    };
+
+    let before = [
+        error(host4(1), host4(2), host4(3)),
+        redirect(host4(1), host4(2), host4(4), host4(3)),
+    ];
+    let after = [
+        error(enc4(host4(1)), enc4(host4(2)), enc4(host4(3))),
+        redirect(
+            enc4(host4(1)),
+            enc4(host4(2)),
+            enc4(host4(4)),
+            enc4(host4(3)),
+        ),
+    ];
+    assert_eq!(
+        rewrite(&pcap(LINKTYPE_IPV4, &before), &keys()),
+        Ok(pcap(LINKTYPE_IPV4, &after))
+    );
+}
+
+#[test]
+fn icmpv6_errors_and_neighbor_discovery_are_rewritten() {
+    let quoted = |src, dst| ipv6(src, dst, &[], &udp(b"milk and cookies"))[..48].to_vec();
+    let error = |router, host, dst| ipv6(router, host, &[], &icmpv6(1, &quoted(host, dst)));
+    let solicit = |host, target: Ipv6Addr| ipv6(host, target, &[], &icmpv6(135, &target.octets()));
+    let redirect = |router, host, target: Ipv6Addr, dst: Ipv6Addr| {
+        let option = [&[4, 7, 0, 0, 0, 0, 0, 0][..], &quoted(host, dst)].concat();
+        let body = [&target.octets()[..], &dst.octets(), &option].concat();
+        ipv6(router, host, &[], &icmpv6(137, &body))
+    };
+
+    let before = [
+        error(host6(1), host6(2), host6(3)),
+        solicit(host6(2), host6(4)),
+        redirect(host6(1), host6(2), host6(4), host6(3)),
+    ];
+    let after = [
+        error(enc6(host6(1)), enc6(host6(2)), enc6(host6(3))),
+        solicit(enc6(host6(2)), enc6(host6(4))),
+        redirect(
+            enc6(host6(1)),
+            enc6(host6(2)),
+            enc6(host6(4)),
+            enc6(host6(3)),
+        ),
+    ];
+    assert_eq!(
+        rewrite(&pcap(LINKTYPE_IPV6, &before), &keys()),
+        Ok(pcap(LINKTYPE_IPV6, &after))
+    );
+}
+
+#[test]
+fn arp_addresses_are_rewritten() {
+    let arp = |sender: Ipv4Addr, target: Ipv4Addr| {
+        let body = [
+            &[0, 1, 8, 0, 6, 4, 0, 1][..],
+            &[2, 0, 0, 0, 0, 1],
+            &sender.octets(),
+            &[0; 6],
+            &target.octets(),
+        ]
+        .concat();
+        ethernet(&[], 0x0806, &body)
+    };
+
+    assert_eq!(
+        rewrite(
+            &pcap(LINKTYPE_ETHERNET, &[arp(host4(1), host4(2))]),
+            &keys()
+        ),
+        Ok(pcap(
+            LINKTYPE_ETHERNET,
+            &[arp(enc4(host4(1)), enc4(host4(2)))]
+        ))
+    );
+}
+
+#[test]
+fn only_the_outermost_quoted_packet_is_rewritten() {
+    // ICMP errors are never sent about ICMP errors, so whatever one quotes
+    // past the first level is left as it is, however deep it goes
+    let nested = |depth: usize, outer: [Ipv4Addr; 3]| {
+        let mut packet = ipv4(host4(5), host4(6), &udp(b"milk"));
+        for _ in 2..depth {
+            packet = ipv4(host4(3), host4(4), &icmp(3, [0; 4], &packet));
+        }
+        let [router, host, dst] = outer;
+        packet = ipv4(host, dst, &icmp(3, [0; 4], &packet));
+        ipv4(router, host, &icmp(3, [0; 4], &packet))
+    };
+    let before = nested(2000, [host4(1), host4(2), host4(3)]);
+    let after = nested(2000, [enc4(host4(1)), enc4(host4(2)), enc4(host4(3))]);
+    assert_eq!(
+        rewrite(&pcap(LINKTYPE_IPV4, &[before]), &keys()),
+        Ok(pcap(LINKTYPE_IPV4, &[after]))
+    );
+
+    let nested = |depth: usize, outer: [Ipv6Addr; 3]| {
+        let mut packet = ipv6(host6(5), host6(6), &[], &udp(b"milk"));
+        for _ in 2..depth {
+            packet = ipv6(host6(3), host6(4), &[], &icmpv6(1, &packet));
+        }
+        let [router, host, dst] = outer;
+        packet = ipv6(host, dst, &[], &icmpv6(1, &packet));
+        ipv6(router, host, &[], &icmpv6(1, &packet))
+    };
+    let before = nested(1000, [host6(1), host6(2), host6(3)]);
+    let after = nested(1000, [enc6(host6(1)), enc6(host6(2)), enc6(host6(3))]);
+    assert_eq!(
+        rewrite(&pcap(LINKTYPE_IPV6, &[before]), &keys()),
+        Ok(pcap(LINKTYPE_IPV6, &[after]))
+    );
+}
+
+#[test]
+fn ipv4_headers_shorter_than_20_bytes_are_left_alone() {
+    for ihl in [0, 4] {
+        let mut packet = ipv4(host4(1), host4(2), &udp(b"milk"));
+        packet[0] = 0x40 | ihl;
+        let capture = pcap(LINKTYPE_IPV4, &[packet]);
+
+        assert_eq!(rewrite(&capture, &keys()), Ok(capture.clone()), "IHL {ihl}");
+    }
+}
@@ -0,0 +1,333 @@
+use std::net::Ipv4Addr;
+
+use axum::{
+    body::{to_bytes, Body},
+    http::{Request, StatusCode},
+};
+use serde_json::{json, Value};
+use shuttlings_cch24::day2::day_2_routes;
+use tower::ServiceExt;
+
+async fn send(request: Request<Body>) -> (StatusCode, String) {
+    let response = day_2_routes().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+async fn get(uri: &str) -> (StatusCode, String) {
+    send(Request::get(uri).body(Body::empty()).unwrap()).await
+}
+
+async fn get_json(uri: &str) -> (StatusCode, Value) {
+    let (status, body) = get(uri).await;
+    (status, serde_json::from_str(&body).unwrap())
+}
+
+async fn post_json(uri: &str, body: Value) -> (StatusCode, Value) {
+    let request = Request::post(uri)
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    let (status, body) = send(request).await;
+    (status, serde_json::from_str(&body).unwrap())
+}
+
+#[tokio::test]
+async fn v6_keys_that_look_mapped_round_trip() {
+    let (status, key) = get("/v6/key?from=2001:db8::1&to=2001:db8::ffff:a00:1").await;
+    assert_eq!((status, key.as_str()), (StatusCode::OK, "::ffff:10.0.0.0"));
+
+    let (status, to) = get(&format!("/v6/dest?from=2001:db8::1&key={key}")).await;
+    assert_eq!(
+        (status, to.as_str()),
+        (StatusCode::OK, "2001:db8::ffff:a00:1")
+    );
+}
+
+#[tokio::test]
+async fn mapped_from_still_takes_v4_keys() {
+    let (status, to) = get("/dest?from=::ffff:10.0.0.1&key=1.2.3.4").await;
+    assert_eq!((status, to.as_str()), (StatusCode::OK, "::ffff:11.2.3.5"));
+
+    let (status, error) = get_json("/dest?from=10.0.0.1&key=2001:db8::1").await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(error["field"], json!("key"));
+}
+
+#[tokio::test]
+async fn subnets_map_to_blocks_when_contiguous() {
+    // (query, network, total hosts, first host mapping)
+    let cases = [
+        (
+            "from=10.0.0.0/30&key=0.0.0.4",
+            json!("10.0.0.4/30"),
+            "4",
+            ["10.0.0.0", "10.0.0.4"],
+        ),
+        // The key's host bits carry into the network within the last octet
+        (
+            "from=10.0.0.0/30&key=0.0.0.1",
+            Value::Null,
+            "4",
+            ["10.0.0.0", "10.0.0.1"],
+        ),
+        (
+            "from=10.0.0.0/30&key=0.0.0.1&op=xor",
+            json!("10.0.0.0/30"),
+            "4",
+            ["10.0.0.0", "10.0.0.1"],
+        ),
+        (
+            "from=10.0.0.0/30&key=0.0.0.0&op=rotl:1",
+            Value::Null,
+            "4",
+            ["10.0.0.0", "20.0.0.0"],
+        ),
+        (
+            "from=::ffff:10.0.0.0/120&key=1.2.3.4",
+            json!("::ffff:11.2.3.0/120"),
+            "256",
+            ["::ffff:10.0.0.0", "::ffff:11.2.3.4"],
+        ),
+        // Wider than ::ffff:0:0/96, so not a block of IPv4 addresses
+        (
+            "from=::ffff:10.0.0.0/95&key=::1",
+            json!("::fffe:0:0/95"),
+            "8589934592",
+            ["::fffe:0:0", "::fffe:0:1"],
+        ),
+    ];
+
+    for (query, network, total_hosts, [from, to]) in cases {
+        let (status, mapping) = get_json(&format!("/dest?{query}")).await;
+        assert_eq!(status, StatusCode::OK, "{query}");
+        assert_eq!(mapping["network"], network, "{query}");
+        assert_eq!(mapping["total_hosts"], json!(total_hosts), "{query}");
+        assert_eq!(
+            mapping["hosts"][0],
+            json!({ "from": from, "to": to }),
+            "{query}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn subnet_pages_stop_at_the_last_host() {
+    let (_, mapping) = get_json("/dest?from=10.0.0.0/30&key=0.0.0.4&per_page=3&page=2").await;
+    assert_eq!(
+        mapping["hosts"],
+        json!([{ "from": "10.0.0.3", "to": "10.0.0.7" }])
+    );
+
+    let (status, mapping) = get_json("/dest?from=10.0.0.0/30&key=0.0.0.4&page=9").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        (mapping["page"].clone(), mapping["hosts"].clone()),
+        (json!(9), json!([]))
+    );
+}
+
+#[tokio::test]
+async fn batch_reports_errors_per_item() {
+    let (status, results) = post_json(
+        "/batch",
+        json!([
+            { "from": "10.0.0.1", "key": "1.2.3.4" },
+            { "from": "2001:db8::1", "to": "2001:db8::2" },
+            { "from": "10.0.0.1", "key": "1.2.3.4", "to": "11.2.3.5" },
+            { "from": "10.0.0.1" },
+            { "from": "10.0.0.256", "key": "1.2.3.4" },
+            { "from": "10.0.0.1", "key": "2001:db8::1" },
+        ]),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        results,
+        json!([
+            { "result": "11.2.3.5" },
+            { "result": "::3" },
+            { "field": "to", "error": "unexpected to" },
+            { "field": "key", "error": "missing key" },
+            { "field": "from", "error": "bad octet '256'" },
+            {
+                "field": "key",
+                "error": "key does not match the address family of the other addresses"
+            },
+        ])
+    );
+}
+
+#[tokio::test]
+async fn solve_finds_the_one_key() {
+    let pair = |from: &str, to: &str| json!({ "from": from, "to": to });
+
+    let (status, body) = post_json(
+        "/solve",
+        json!([pair("10.0.0.1", "11.2.3.5"), pair("10.0.0.2", "11.2.3.6")]),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        body,
+        json!({ "key": "1.2.3.4", "op": "add", "observations": 2 })
+    );
+
+    let (status, body) = post_json(
+        "/solve",
+        json!([
+            pair("10.0.0.1", "11.2.3.5"),
+            pair("10.0.0.3", "10.0.0.3"),
+            pair("10.0.0.2", "11.2.3.6"),
+        ]),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(
+        body,
+        json!({
+            "op": "add",
+            "candidates": [
+                {
+                    "key": "1.2.3.4",
+                    "pairs": [
+                        { "index": 0, "from": "10.0.0.1", "to": "11.2.3.5" },
+                        { "index": 2, "from": "10.0.0.2", "to": "11.2.3.6" },
+                    ],
+                },
+                {
+                    "key": "0.0.0.0",
+                    "pairs": [{ "index": 1, "from": "10.0.0.3", "to": "10.0.0.3" }],
+                },
+            ],
+        })
+    );
+}
+
+#[tokio::test]
+async fn solve_rejects_bad_observations() {
+    let cases = [
+        (
+            json!([{ "from": "10.0.0.1", "to": "11.2.3.5" }, { "from": "10.0.0.1" }]),
+            StatusCode::BAD_REQUEST,
+            json!({ "index": 1, "field": "to", "error": "missing to" }),
+        ),
+        (
+            json!([
+                { "from": "10.0.0.1", "to": "11.2.3.5" },
+                { "from": "2001:db8::1", "to": "2001:db8::2" },
+            ]),
+            StatusCode::UNPROCESSABLE_ENTITY,
+            json!({
+                "index": 1,
+                "field": "from",
+                "error": "from does not match the address family of the other addresses",
+            }),
+        ),
+        (
+            json!([]),
+            StatusCode::BAD_REQUEST,
+            json!({ "field": "observations", "error": "missing observations" }),
+        ),
+    ];
+
+    for (observations, status, error) in cases {
+        assert_eq!(post_json("/solve", observations).await, (status, error));
+    }
+}
+
+#[tokio::test]
+async fn routes_keep_mapped_notation() {
+    let (status, route) = get_json("/route?from=::ffff:10.0.0.1&keys=1.2.3.4,0.0.0.1").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        route,
+        json!({
+            "from": "::ffff:10.0.0.1",
+            "op": "add",
+            "hops": [
+                { "key": "::ffff:1.2.3.4", "to": "::ffff:11.2.3.5" },
+                { "key": "::ffff:0.0.0.1", "to": "::ffff:11.2.3.6" },
+            ],
+            "to": "::ffff:11.2.3.6",
+            "composite": { "op": "add", "key": "::ffff:1.2.3.5" },
+        })
+    );
+}
+
+#[tokio::test]
+async fn route_keys_must_all_be_usable() {
+    for query in [
+        "from=10.0.0.1&keys=1.1.1.1,2001:db8::1",
+        "from=2001:db8::1&keys=::1,1.1.1.1",
+    ] {
+        assert_eq!(
+            get_json(&format!("/route?{query}")).await,
+            (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                json!({
+                    "field": "keys",
+                    "error": "keys does not match the address family of the other addresses",
+                })
+            ),
+            "{query}"
+        );
+    }
+
+    for query in ["from=10.0.0.1&keys=1.1.1.1,,2.2.2.2", "from=10.0.0.1&keys="] {
+        assert_eq!(
+            get_json(&format!("/route?{query}")).await,
+            (
+                StatusCode::BAD_REQUEST,
+                json!({ "field": "keys", "error": "bad octet ''" })
+            ),
+            "{query}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn rotl_routes_list_every_hop_and_compose() {
+    let rotl3 = |addr: Ipv4Addr, key: u32| Ipv4Addr::from(u32::from(addr).rotate_left(3) ^ key);
+    let from = Ipv4Addr::new(10, 0, 0, 1);
+    let hops = [1, 2, 3]
+        .iter()
+        .scan(from, |addr, &key| {
+            *addr = rotl3(*addr, key);
+            Some(json!({ "key": Ipv4Addr::from(key).to_string(), "to": addr.to_string() }))
+        })
+        .collect::<Vec<_>>();
+
+    let (status, route) =
+        get_json("/route?from=10.0.0.1&keys=0.0.0.1,0.0.0.2,0.0.0.3&op=rotl:3").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(route["hops"], json!(hops));
+    assert_eq!(route["to"], hops[2]["to"]);
+
+    // Three rotations of 3 are one of 9, with the keys rotated along
+    assert_eq!(
+        route["composite"],
+        json!({ "op": "rotl:9", "key": "0.0.0.83" })
+    );
+    let (_, to) = get("/dest?from=10.0.0.1&key=0.0.0.83&op=rotl:9").await;
+    assert_eq!(json!(to), route["to"]);
+
+    let (_, route) = get_json("/route?from=2001:db8::1&keys=::1,::2&op=rotl:8").await;
+    assert_eq!(
+        route["hops"],
+        json!([
+            { "key": "::1", "to": "10d:b800::121" },
+            { "key": "::2", "to": "db8::1:2103" },
+        ])
+    );
+    let composite = &route["composite"];
+    let (_, to) = get(&format!(
+        "/v6/dest?from=2001:db8::1&key={}&op={}",
+        composite["key"].as_str().unwrap(),
+        composite["op"].as_str().unwrap()
+    ))
+    .await;
+    assert_eq!(json!(to), route["to"]);
+}
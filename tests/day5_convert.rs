@@ -0,0 +1,219 @@
+use axum::{
+    body::{to_bytes, Body},
+    http::{header::CONTENT_TYPE, Request, StatusCode},
+};
+use serde_json::Value;
+use shuttlings_cch24::day5::day_05_routes;
+use tower::ServiceExt;
+
+async fn convert(from: &str, to: &str, manifest: &str) -> (StatusCode, Option<String>, String) {
+    let request = Request::post("/convert")
+        .header("content-type", from)
+        .header("accept", to)
+        .body(Body::from(manifest.to_string()))
+        .unwrap();
+
+    let response = day_05_routes().oneshot(request).await.unwrap();
+    let status = response.status();
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .map(|ct| ct.to_str().unwrap().to_string());
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (
+        status,
+        content_type,
+        String::from_utf8(body.to_vec()).unwrap(),
+    )
+}
+
+const TOML_TYPE: &str = "application/toml";
+const JSON_TYPE: &str = "application/json";
+const YAML_TYPE: &str = "application/yaml";
+
+const TOML: &str = r#"# The gift shop
+[package]
+name = "gifts"
+version = "0.1.0" # bumped by hand
+edition = "2021"
+
+# Wrapping comes before ribbons
+[dependencies]
+wrapping = "1"
+ribbons = { version = "0.2", features = ["curly"] }
+
+[[package.metadata.orders]]
+item = "Toy car"
+quantity = 2
+"#;
+
+/// Every key path in `value`, in the order the document has them.
+fn key_paths(value: &Value, prefix: &str, out: &mut Vec<String>) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                let path = format!("{prefix}.{key}");
+                out.push(path.clone());
+                key_paths(value, &path, out);
+            }
+        }
+        Value::Array(items) => {
+            for (i, item) in items.iter().enumerate() {
+                key_paths(item, &format!("{prefix}[{i}]"), out);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn parse(media_type: &str, data: &str) -> Value {
+    match media_type {
+        TOML_TYPE => toml::from_str(data).unwrap(),
+        JSON_TYPE => serde_json::from_str(data).unwrap(),
+        _ => serde_yaml::from_str(data).unwrap(),
+    }
+}
+
+#[tokio::test]
+async fn every_direction_keeps_the_data_and_key_order() {
+    let expected = parse(TOML_TYPE, TOML);
+    let mut order = vec![];
+    key_paths(&expected, "", &mut order);
+    // Not sorted: the manifest's own order
+    assert_eq!(
+        order[..4],
+        [
+            ".package",
+            ".package.name",
+            ".package.version",
+            ".package.edition"
+        ]
+    );
+    assert!(order.contains(&".dependencies.wrapping".to_string()));
+
+    let formats = [TOML_TYPE, JSON_TYPE, YAML_TYPE];
+    for from in formats {
+        let (_, _, source) = convert(TOML_TYPE, from, TOML).await;
+        for to in formats {
+            let (status, content_type, converted) = convert(from, to, &source).await;
+            assert_eq!(status, StatusCode::OK, "{from} to {to}");
+            assert_eq!(content_type.as_deref(), Some(to), "{from} to {to}");
+
+            let value = parse(to, &converted);
+            assert_eq!(value, expected, "{from} to {to}");
+            let mut keys = vec![];
+            key_paths(&value, "", &mut keys);
+            let in_order = |keys: &[String], table: &str| {
+                keys.iter()
+                    .filter(|k| k.starts_with(table))
+                    .cloned()
+                    .collect::<Vec<_>>()
+            };
+            for table in [".package.", ".dependencies."] {
+                assert_eq!(
+                    in_order(&keys, table),
+                    in_order(&order, table),
+                    "{from} to {to}"
+                );
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn toml_comments_are_carried_into_yaml() {
+    let (_, _, yaml) = convert(TOML_TYPE, YAML_TYPE, TOML).await;
+    assert_eq!(
+        yaml,
+        r#"# The gift shop
+package:
+  name: gifts
+  version: 0.1.0 # bumped by hand
+  edition: '2021'
+  metadata:
+    orders:
+      - item: Toy car
+        quantity: 2
+# Wrapping comes before ribbons
+dependencies:
+  wrapping: '1'
+  ribbons:
+    version: '0.2'
+    features:
+    - curly
+"#
+    );
+}
+
+#[tokio::test]
+async fn yaml_comments_above_keys_are_carried_into_toml() {
+    let yaml = r#"# The gift shop
+package:
+  # As published
+  name: gifts
+  version: 0.1.0 # trailing comments have nowhere to go
+  metadata:
+    orders:
+      # Nor do comments inside sequences
+      - item: Toy car
+        quantity: 2
+# Wrapping comes before ribbons
+dependencies:
+  wrapping: '1'
+# The end
+"#;
+    let (status, _, toml) = convert(YAML_TYPE, TOML_TYPE, yaml).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        toml,
+        r#"# The gift shop
+[package]
+# As published
+name = "gifts"
+version = "0.1.0"
+
+[[package.metadata.orders]]
+item = "Toy car"
+quantity = 2
+
+# Wrapping comes before ribbons
+[dependencies]
+wrapping = "1"
+# The end
+"#
+    );
+}
+
+#[tokio::test]
+async fn conversions_that_cant_be_made_are_refused() {
+    let manifest = r#"{ "package": { "name": "gifts", "version": "0.1.0" } }"#;
+
+    let (status, _, _) = convert("text/plain", TOML_TYPE, manifest).await;
+    assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    let (status, _, _) = convert(JSON_TYPE, "text/html", manifest).await;
+    assert_eq!(status, StatusCode::NOT_ACCEPTABLE);
+    // TOML has no null
+    let nulled = r#"{ "package": { "name": "gifts", "version": "0.1.0", "description": null } }"#;
+    let (status, _, message) = convert(JSON_TYPE, TOML_TYPE, nulled).await;
+    assert_eq!(
+        (status, message.as_str()),
+        (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "could not convert data: unsupported unit type"
+        )
+    );
+    // YAML does
+    let (status, _, yaml) = convert(JSON_TYPE, YAML_TYPE, nulled).await;
+    assert_eq!(
+        (status, yaml.as_str()),
+        (
+            StatusCode::OK,
+            "package:\n  name: gifts\n  version: 0.1.0\n  description: null\n"
+        )
+    );
+
+    let (status, _, _) = convert(JSON_TYPE, TOML_TYPE, "{").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _, _) = convert(JSON_TYPE, TOML_TYPE, r#"{ "package": 1 }"#).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
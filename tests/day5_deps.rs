@@ -0,0 +1,180 @@
+use axum::{
+    body::{to_bytes, Body},
+    http::{header::CONTENT_TYPE, Request, StatusCode},
+};
+use serde_json::{json, Value};
+use shuttlings_cch24::day5::day_05_routes;
+use tower::ServiceExt;
+
+async fn deps(accept: Option<&str>, manifest: &str) -> (StatusCode, Option<String>, String) {
+    let mut request = Request::post("/deps").header("content-type", "application/toml");
+    if let Some(accept) = accept {
+        request = request.header("accept", accept);
+    }
+    let request = request.body(Body::from(manifest.to_string())).unwrap();
+
+    let response = day_05_routes().oneshot(request).await.unwrap();
+    let status = response.status();
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .map(|ct| ct.to_str().unwrap().to_string());
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (
+        status,
+        content_type,
+        String::from_utf8(body.to_vec()).unwrap(),
+    )
+}
+
+const MANIFEST: &str = r#"
+[package]
+name = "gifts"
+version = "0.1.0"
+
+[dependencies]
+serde = "1"
+json = { version = "1", package = "serde_json", optional = true }
+wrapping = { path = "../wrapping", default-features = false, features = ["bows"] }
+
+[dev-dependencies]
+proptest = "1"
+serde = { version = "1", features = ["derive"] }
+
+[build-dependencies]
+cc = { git = "https://github.com/rust-lang/cc-rs" }
+
+[target.'cfg(target_os = "linux")'.dependencies]
+libc = "0.2"
+"#;
+
+#[tokio::test]
+async fn every_kind_of_dependency_is_an_edge() {
+    let (status, content_type, body) = deps(None, MANIFEST).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(content_type.as_deref(), Some("application/json"));
+    let graph: Value = serde_json::from_str(&body).unwrap();
+
+    assert_eq!(graph["package"], "gifts");
+    // One node per package, however many times it is declared
+    assert_eq!(
+        graph["nodes"],
+        json!([
+            { "id": "serde_json", "source": "registry" },
+            { "id": "serde", "source": "registry" },
+            { "id": "wrapping", "source": "path+../wrapping" },
+            { "id": "proptest", "source": "registry" },
+            { "id": "cc", "source": "git+https://github.com/rust-lang/cc-rs" },
+            { "id": "libc", "source": "registry" },
+        ])
+    );
+
+    let edges = graph["edges"].as_array().unwrap();
+    let edge = |name: &str, kind: &str| {
+        edges
+            .iter()
+            .find(|e| e["name"] == name && e["kind"] == kind)
+            .unwrap_or_else(|| panic!("no {kind} edge {name}"))
+    };
+    assert_eq!(edges.len(), 7);
+    assert_eq!(
+        edge("json", "normal"),
+        &json!({
+            "name": "json",
+            "to": "serde_json",
+            "kind": "normal",
+            "target": null,
+            "requirement": "1",
+            "optional": true,
+            "default_features": true,
+            "features": [],
+        })
+    );
+    assert_eq!(
+        (
+            &edge("wrapping", "normal")["default_features"],
+            &edge("wrapping", "normal")["features"]
+        ),
+        (&json!(false), &json!(["bows"]))
+    );
+    assert_eq!(edge("serde", "dev")["features"], json!(["derive"]));
+    assert_eq!(edge("proptest", "dev")["requirement"], "1");
+    assert_eq!(edge("cc", "build")["requirement"], Value::Null);
+    assert_eq!(
+        edge("libc", "normal")["target"],
+        r#"cfg(target_os = "linux")"#
+    );
+}
+
+#[tokio::test]
+async fn graphs_are_rendered_as_asked() {
+    let (status, content_type, dot) = deps(Some("text/vnd.graphviz"), MANIFEST).await;
+    assert_eq!(
+        (status, content_type.as_deref()),
+        (StatusCode::OK, Some("text/vnd.graphviz"))
+    );
+    assert!(dot.starts_with("digraph \"gifts\" {\n"), "{dot}");
+    for line in [
+        r#"    "cc" [tooltip="git+https://github.com/rust-lang/cc-rs"];"#,
+        r#"    "gifts" -> "serde_json" [label="normal, as json, 1, optional", color=black, style=dashed];"#,
+        r#"    "gifts" -> "serde" [label="dev, 1, features: derive", color=blue, style=solid];"#,
+        r#"    "gifts" -> "cc" [label="build", color=darkgreen, style=solid];"#,
+        r#"    "gifts" -> "libc" [label="normal, 0.2, cfg(target_os = \"linux\")", color=black, style=solid];"#,
+    ] {
+        assert!(dot.lines().any(|l| l == line), "{line}\n{dot}");
+    }
+
+    let (status, content_type, mermaid) = deps(Some("text/vnd.mermaid"), MANIFEST).await;
+    assert_eq!(
+        (status, content_type.as_deref()),
+        (StatusCode::OK, Some("text/vnd.mermaid"))
+    );
+    assert!(mermaid.starts_with("graph LR\n    root[\"gifts\"]\n    n0[\"serde_json\"]\n"));
+    for line in [
+        r#"    root -.->|"normal, as json, 1, optional"| n0"#,
+        r#"    root -->|"normal, no default features, features: bows"| n2"#,
+        r#"    root -->|"normal, 0.2, cfg(target_os = #quot;linux#quot;)"| n5"#,
+    ] {
+        assert!(mermaid.lines().any(|l| l == line), "{line}\n{mermaid}");
+    }
+
+    // The most preferred format the client accepts wins
+    let (_, content_type, _) =
+        deps(Some("application/json;q=0.5, text/vnd.mermaid"), MANIFEST).await;
+    assert_eq!(content_type.as_deref(), Some("text/vnd.mermaid"));
+    let (status, _, _) = deps(Some("image/png"), MANIFEST).await;
+    assert_eq!(status, StatusCode::NOT_ACCEPTABLE);
+}
+
+#[tokio::test]
+async fn names_are_quoted_for_each_format() {
+    let manifest = r#"
+[package]
+name = 'say "cheese"'
+version = "0.1.0"
+
+[dependencies]
+'pipe|dream' = { version = "1", package = 'back\slash' }
+"#;
+
+    let (_, _, dot) = deps(Some("text/vnd.graphviz"), manifest).await;
+    assert_eq!(
+        dot,
+        r#"digraph "say \"cheese\"" {
+    "say \"cheese\"" [shape=box];
+    "back\\slash" [tooltip="registry"];
+    "say \"cheese\"" -> "back\\slash" [label="normal, as pipe|dream, 1", color=black, style=solid];
+}
+"#
+    );
+
+    let (_, _, mermaid) = deps(Some("text/vnd.mermaid"), manifest).await;
+    assert_eq!(
+        mermaid,
+        r#"graph LR
+    root["say #quot;cheese#quot;"]
+    n0["back\slash"]
+    root -->|"normal, as pipe#124;dream, 1"| n0
+"#
+    );
+}
@@ -0,0 +1,203 @@
+use axum::{
+    body::{to_bytes, Body},
+    http::{Request, StatusCode},
+};
+use serde_json::{json, Value};
+use shuttlings_cch24::day5::day_05_routes;
+use tower::ServiceExt;
+
+const MANIFEST: &str = r#"
+[package]
+name = "gifts"
+version = "0.1.0"
+
+[dependencies]
+serde = { version = "1", optional = true }
+serde_json = { version = "1", optional = true }
+rand = { version = "0.8", optional = true }
+tokio = "1"
+log = { version = "0.4", optional = true }
+
+[features]
+default = ["std"]
+std = ["serde?/std", "rand?/std"]
+json = ["dep:serde_json", "serde/derive"]
+logging = ["dep:log"]
+fast = ["tokio/rt-multi-thread"]
+full = ["json", "logging", "rand"]
+"#;
+
+async fn features(query: &str, manifest: &str) -> (StatusCode, String) {
+    let request = Request::post(format!("/features{query}"))
+        .header("content-type", "application/toml")
+        .body(Body::from(manifest.to_string()))
+        .unwrap();
+
+    let response = day_05_routes().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+/// The resolution of `query` against [`MANIFEST`].
+async fn resolve(query: &str) -> Value {
+    let (status, body) = features(query, MANIFEST).await;
+    assert_eq!(status, StatusCode::OK, "{query}: {body}");
+    serde_json::from_str(&body).unwrap()
+}
+
+#[tokio::test]
+async fn defaults_are_on_unless_turned_off() {
+    assert_eq!(
+        resolve("").await,
+        json!({
+            "requested": ["default"],
+            "features": ["default", "std"],
+            "optional_dependencies": [],
+            "dependency_features": {},
+        })
+    );
+
+    let resolution = resolve("?no-default-features=true&features=fast").await;
+    assert_eq!(
+        (&resolution["requested"], &resolution["features"]),
+        (&json!(["fast"]), &json!(["fast"]))
+    );
+    // A feature of a dependency that isn't optional turns nothing on
+    assert_eq!(resolution["optional_dependencies"], json!([]));
+    assert_eq!(
+        resolution["dependency_features"],
+        json!({ "tokio": ["rt-multi-thread"] })
+    );
+
+    let resolution = resolve("?all-features=true").await;
+    assert_eq!(
+        resolution["features"],
+        json!(["default", "fast", "full", "json", "logging", "rand", "serde", "std"])
+    );
+    assert_eq!(resolution["requested"], resolution["features"]);
+}
+
+#[tokio::test]
+async fn dep_prefix_hides_the_implicit_feature() {
+    // `log` and `serde_json` are only named with `dep:`
+    for feature in ["log", "serde_json"] {
+        assert_eq!(
+            features(&format!("?features={feature}"), MANIFEST).await,
+            (
+                StatusCode::BAD_REQUEST,
+                format!("Unknown feature `{feature}`")
+            )
+        );
+    }
+
+    let resolution = resolve("?no-default-features=true&features=rand").await;
+    assert_eq!(resolution["features"], json!(["rand"]));
+    assert_eq!(
+        resolution["optional_dependencies"],
+        json!([{ "name": "rand", "because": ["rand"] }])
+    );
+}
+
+#[tokio::test]
+async fn dependency_features_turn_optional_dependencies_on() {
+    let resolution = resolve("?no-default-features=true&features=json").await;
+    // `serde/derive` turns serde on, and with it serde's implicit feature
+    assert_eq!(resolution["features"], json!(["json", "serde"]));
+    assert_eq!(
+        resolution["optional_dependencies"],
+        json!([
+            { "name": "serde", "because": ["json"] },
+            { "name": "serde_json", "because": ["json"] },
+        ])
+    );
+    assert_eq!(
+        resolution["dependency_features"],
+        json!({ "serde": ["derive"] })
+    );
+}
+
+#[tokio::test]
+async fn weak_dependency_features_stay_weak() {
+    // `std` asks for `serde?/std` and `rand?/std`, neither of which is on
+    let resolution = resolve("?features=std").await;
+    assert_eq!(resolution["optional_dependencies"], json!([]));
+    assert_eq!(resolution["dependency_features"], json!({}));
+
+    // Until something else turns serde on
+    let resolution = resolve("?features=json").await;
+    assert_eq!(
+        resolution["dependency_features"],
+        json!({ "serde": ["derive", "std"] })
+    );
+}
+
+#[tokio::test]
+async fn activations_say_which_features_led_to_them() {
+    let resolution = resolve("?features=full").await;
+    assert_eq!(
+        resolution["optional_dependencies"],
+        json!([
+            { "name": "log", "because": ["full", "logging"] },
+            { "name": "rand", "because": ["full", "rand"] },
+            { "name": "serde", "because": ["full", "json"] },
+            { "name": "serde_json", "because": ["full", "json"] },
+        ])
+    );
+}
+
+#[tokio::test]
+async fn cycles_resolve_and_unknown_names_are_errors() {
+    let manifest = |features: &str| {
+        format!(
+            r#"
+[package]
+name = "gifts"
+version = "0.1.0"
+
+[dependencies]
+tokio = "1"
+log = {{ version = "0.4", optional = true }}
+
+[features]
+{features}
+"#
+        )
+    };
+
+    let cyclic = manifest("wrap = [\"bow\"]\nbow = [\"wrap\", \"dep:log\"]");
+    let (status, body) = features("?features=wrap", &cyclic).await;
+    assert_eq!(status, StatusCode::OK, "{body}");
+    let resolution: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(resolution["features"], json!(["bow", "wrap"]));
+    assert_eq!(
+        resolution["optional_dependencies"],
+        json!([{ "name": "log", "because": ["wrap", "bow"] }])
+    );
+
+    let cases = [
+        (
+            "broken = [\"missing\"]",
+            "Unknown feature `missing` in feature `broken`",
+        ),
+        (
+            "broken = [\"dep:tokio\"]",
+            "Unknown optional dependency `tokio` in feature `broken`",
+        ),
+        (
+            "broken = [\"sleigh/fast\"]",
+            "Unknown optional dependency `sleigh` in feature `broken`",
+        ),
+        (
+            "broken = [\"sleigh?/fast\"]",
+            "Unknown optional dependency `sleigh` in feature `broken`",
+        ),
+    ];
+    for (features_table, message) in cases {
+        assert_eq!(
+            features("?features=broken", &manifest(features_table)).await,
+            (StatusCode::BAD_REQUEST, message.to_string()),
+            "{features_table}"
+        );
+    }
+}
@@ -0,0 +1,134 @@
+use axum::{
+    body::{to_bytes, Body},
+    http::{header::CONTENT_TYPE, Request, StatusCode},
+};
+use shuttlings_cch24::day5::day_05_routes;
+use tower::ServiceExt;
+
+const MESSY: &str = r#"[package]
+version = "0.1.0" # bumped by hand
+edition = "2021"
+name = "gifts"
+
+[dependencies]
+# Serialization
+[dependencies.serde]
+# derive is needed for the orders schema
+features = ["derive"]
+version = "1"
+
+[dependencies.anyhow]
+version = "1.0"
+"#;
+
+async fn fmt(uri: &str, manifest: &str) -> (StatusCode, Option<String>, String) {
+    let request = Request::post(uri)
+        .header("content-type", "application/toml")
+        .body(Body::from(manifest.to_string()))
+        .unwrap();
+
+    let response = day_05_routes().oneshot(request).await.unwrap();
+    let status = response.status();
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .map(|ct| ct.to_str().unwrap().to_string());
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (
+        status,
+        content_type,
+        String::from_utf8(body.to_vec()).unwrap(),
+    )
+}
+
+const FORMATTED: &str = r#"[package]
+name = "gifts"
+version = "0.1.0" # bumped by hand
+edition = "2021"
+
+[dependencies]
+anyhow = "1.0"
+# Serialization
+# derive is needed for the orders schema
+serde = { features = ["derive"], version = "1" }
+"#;
+
+#[tokio::test]
+async fn dependency_tables_become_inline_with_their_comments() {
+    assert_eq!(
+        fmt("/fmt", MESSY).await,
+        (
+            StatusCode::OK,
+            Some("application/toml".to_string()),
+            FORMATTED.to_string()
+        )
+    );
+
+    let manifest = "[package]\nname = \"gifts\"\nversion = \"0.1.0\"\n\n\
+                    [dev-dependencies.tokio] # tests only\nversion = \"1\"\n";
+    let (_, _, formatted) = fmt("/fmt", manifest).await;
+    assert!(
+        formatted.ends_with("\n[dev-dependencies]\n# tests only\ntokio = \"1\"\n"),
+        "{formatted}"
+    );
+}
+
+#[tokio::test]
+async fn formatting_is_idempotent() {
+    let others = r#"[package]
+name = "gifts"
+version = "0.1.0"
+
+[target.'cfg(unix)'.dependencies]
+libc = { version = "0.2" } # system calls
+
+[dev-dependencies]
+proptest = "1"
+assert_cmd = { version = "2", features = [ "color" ] }
+[dev-dependencies.tokio] # tests only
+version = "1"
+features = ["macros"]
+"#;
+
+    for manifest in [MESSY, others] {
+        let (_, _, once) = fmt("/fmt", manifest).await;
+        let (_, _, twice) = fmt("/fmt", &once).await;
+        assert_eq!(once, twice);
+        assert_eq!(
+            fmt("/fmt?check=true", &once).await,
+            (StatusCode::OK, None, String::new())
+        );
+    }
+}
+
+#[tokio::test]
+async fn check_mode_answers_with_a_diff() {
+    let (status, content_type, diff) = fmt("/fmt?check=true", MESSY).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(content_type.as_deref(), Some("text/x-diff"));
+
+    let lines = diff.lines().collect::<Vec<_>>();
+    assert_eq!(lines[..2], ["--- Cargo.toml", "+++ Cargo.toml"]);
+    for line in [
+        "-name = \"gifts\"",
+        "+name = \"gifts\"",
+        "-[dependencies.serde]",
+        "+serde = { features = [\"derive\"], version = \"1\" }",
+        " # derive is needed for the orders schema",
+    ] {
+        assert!(lines.contains(&line), "{line}");
+    }
+}
+
+#[tokio::test]
+async fn only_valid_toml_manifests_are_formatted() {
+    let request = Request::post("/fmt")
+        .header("content-type", "application/json")
+        .body(Body::from("{}"))
+        .unwrap();
+    let response = day_05_routes().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+    let (status, _, _) = fmt("/fmt", "[package\n").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
@@ -0,0 +1,82 @@
+use std::env;
+
+use axum::{
+    body::{to_bytes, Body},
+    http::{Request, StatusCode},
+};
+use serde_json::{json, Value};
+use shuttlings_cch24::day5::day_05_routes;
+use tower::ServiceExt;
+
+const MANIFEST: &str = r#"
+[package]
+name = "not-a-gift-order"
+authors = ["Not Santa"]
+keywords = ["Christmas 2024"]
+
+[[package.metadata.orders]]
+item = "Toy car"
+quantity = 2
+"#;
+
+async fn manifest(accept: Option<&str>) -> (StatusCode, String) {
+    // No catalogue, so no prices
+    env::set_var(
+        "DAY5_PRICES",
+        concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/none.toml"),
+    );
+    let mut request = Request::post("/manifest").header("content-type", "application/toml");
+    if let Some(accept) = accept {
+        request = request.header("accept", accept);
+    }
+    let request = request.body(Body::from(MANIFEST)).unwrap();
+
+    let response = day_05_routes().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+#[tokio::test]
+async fn json_report_follows_accept_weights() {
+    let plain = [
+        None,
+        Some("text/plain"),
+        Some("application/json;q=0"),
+        Some("text/plain, application/json;q=0.1"),
+        Some("text/html"),
+    ];
+    for accept in plain {
+        assert_eq!(
+            manifest(accept).await,
+            (StatusCode::OK, "Toy car: 2".to_string()),
+            "{accept:?}"
+        );
+    }
+
+    let json = [
+        "application/json",
+        "text/plain;q=0.5, application/json",
+        "application/*",
+    ];
+    for accept in json {
+        let (status, body) = manifest(Some(accept)).await;
+        assert_eq!(status, StatusCode::OK, "{accept}");
+        let report: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(report["valid"], Value::Bool(true), "{accept}");
+    }
+}
+
+#[tokio::test]
+async fn without_a_catalogue_orders_are_not_priced() {
+    let (status, body) = manifest(Some("application/json")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        serde_json::from_str::<Value>(&body).unwrap(),
+        json!({
+            "valid": true,
+            "problems": [],
+            "orders": [{ "item": "Toy car", "quantity": 2 }],
+        })
+    );
+}
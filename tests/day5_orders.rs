@@ -0,0 +1,123 @@
+use std::num::NonZeroU32;
+
+use axum::{
+    body::{to_bytes, Body},
+    http::{Request, StatusCode},
+};
+use serde_json::{json, Value};
+use shuttlings_cch24::day5::{
+    day_05_routes,
+    orders::{aggregate, Catalogue, Order},
+};
+use tower::ServiceExt;
+
+fn order(item: &str, quantity: u32) -> Order {
+    Order {
+        item: item.to_string(),
+        quantity: NonZeroU32::new(quantity).unwrap(),
+    }
+}
+
+#[test]
+fn orders_for_one_item_are_summed_in_first_seen_order() {
+    let orders = [
+        order("Toy train", 1),
+        order("Toy car", 2),
+        order("Toy train", 4),
+    ];
+    let lines = aggregate(&orders, None)
+        .into_iter()
+        .map(|l| (l.item, l.quantity, l.unit_price, l.line_total))
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        lines,
+        [
+            ("Toy train".to_string(), 5, None, None),
+            ("Toy car".to_string(), 2, None, None),
+        ]
+    );
+}
+
+#[test]
+fn prices_are_rounded_to_cents() {
+    // 0.29 * 100.0 is 28.999999999999996 in floating point
+    let catalogue = Catalogue::parse(
+        r#"
+        [prices]
+        "Lego brick" = 0.29
+        "Teddy bear" = 19.95
+        "Toy car" = 12.499
+        "Broken" = -1.0
+        "Not a number" = nan
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(catalogue.currency, None);
+    assert_eq!(catalogue.price("Lego brick"), Some(29));
+    assert_eq!(catalogue.price("Teddy bear"), Some(1995));
+    assert_eq!(catalogue.price("Toy car"), Some(1250));
+    assert_eq!(catalogue.price("Broken"), None);
+    assert_eq!(catalogue.price("Not a number"), None);
+
+    let lines = aggregate(&[order("Lego brick", 3)], Some(&catalogue));
+    assert_eq!(
+        (lines[0].unit_price, lines[0].line_total),
+        (Some(29), Some(87))
+    );
+    assert_eq!(
+        serde_json::to_value(&lines[0]).unwrap(),
+        json!({ "item": "Lego brick", "quantity": 3, "unit_price": 0.29, "line_total": 0.87 })
+    );
+}
+
+#[test]
+fn shipped_catalogue_loads() {
+    let catalogue = Catalogue::shared().unwrap().unwrap();
+    assert_eq!(catalogue.currency.as_deref(), Some("EUR"));
+    assert_eq!(catalogue.price("Teddy bear"), Some(1995));
+}
+
+#[tokio::test]
+async fn json_report_totals_priced_orders_and_lists_unpriced_ones() {
+    let manifest = r#"
+[package]
+name = "not-a-gift-order"
+authors = ["Not Santa"]
+keywords = ["Christmas 2024"]
+
+[[package.metadata.orders]]
+item = "Toy car"
+quantity = 2
+
+[[package.metadata.orders]]
+item = "Snow globe"
+quantity = 1
+
+[[package.metadata.orders]]
+item = "Teddy bear"
+quantity = 3
+"#;
+    let request = Request::post("/manifest")
+        .header("content-type", "application/toml")
+        .header("accept", "application/json")
+        .body(Body::from(manifest))
+        .unwrap();
+    let response = day_05_routes().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let report: Value = serde_json::from_slice(&body).unwrap();
+
+    assert_eq!(
+        report["orders"],
+        json!([
+            { "item": "Toy car", "quantity": 2, "unit_price": 12.5, "line_total": 25.0 },
+            { "item": "Snow globe", "quantity": 1 },
+            { "item": "Teddy bear", "quantity": 3, "unit_price": 19.95, "line_total": 59.85 },
+        ])
+    );
+    assert_eq!(report["total"], json!(84.85));
+    assert_eq!(report["currency"], json!("EUR"));
+    assert_eq!(report["unpriced"], json!(["Snow globe"]));
+}
@@ -0,0 +1,118 @@
+use std::{collections::HashMap, env};
+
+use axum::{
+    body::{to_bytes, Body},
+    http::{Request, StatusCode},
+};
+use serde_json::{json, Value};
+use shuttlings_cch24::day5::day_05_routes;
+use tower::ServiceExt;
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+
+/// Checks `manifest` against the fixture index, by dependency name.
+async fn outdated(manifest: &str) -> HashMap<String, Value> {
+    env::set_var("DAY5_INDEX", format!("{FIXTURES}/day5_index"));
+    let request = Request::post("/outdated")
+        .header("content-type", "application/toml")
+        .body(Body::from(manifest.to_string()))
+        .unwrap();
+
+    let response = day_05_routes().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+
+    body["dependencies"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|check| (check["name"].as_str().unwrap().to_string(), check.clone()))
+        .collect()
+}
+
+#[tokio::test]
+async fn requirements_are_checked_against_the_index() {
+    let checks = outdated(
+        r#"
+[package]
+name = "gifts"
+version = "0.1.0"
+
+[dependencies]
+serde = "1.0.150"
+rand = "=0.8.4"
+log = "0.4.20"
+inflector = { version = "0.11", package = "Inflector" }
+tokio = "1"
+local = { path = "../local" }
+
+[dev-dependencies]
+cc = "2"
+
+[build-dependencies]
+cc-build = { version = "latest", package = "cc" }
+"#,
+    )
+    .await;
+
+    let status = |name: &str| checks[name]["status"].as_str().unwrap().to_string();
+    // Yanked versions are never resolved to or suggested
+    assert_eq!(
+        checks["serde"],
+        json!({
+            "name": "serde",
+            "package": "serde",
+            "kind": "normal",
+            "target": null,
+            "requirement": "1.0.150",
+            "status": "ok",
+            "resolved": "1.0.190",
+            "compatible_upgrade": null,
+            "incompatible_upgrade": null,
+        })
+    );
+    // Past the pin, and past compatibility; pre-releases are skipped
+    assert_eq!(
+        (
+            &checks["rand"]["resolved"],
+            &checks["rand"]["compatible_upgrade"],
+            &checks["rand"]["incompatible_upgrade"],
+        ),
+        (&json!("0.8.4"), &json!("0.8.5"), &json!("0.9.0"))
+    );
+    assert_eq!(status("log"), "yanked-only");
+    assert_eq!(
+        (status("inflector"), &checks["inflector"]["resolved"]),
+        ("ok".to_string(), &json!("0.11.4"))
+    );
+    assert_eq!(status("tokio"), "not-found");
+    assert!(!checks.contains_key("local"));
+    assert_eq!(status("cc"), "no-match");
+    assert_eq!(
+        (status("cc-build"), &checks["cc-build"]["kind"]),
+        ("invalid-requirement".to_string(), &json!("build"))
+    );
+}
+
+#[tokio::test]
+async fn names_that_are_not_crate_names_stay_inside_the_index() {
+    let checks = outdated(&format!(
+        r#"
+[package]
+name = "gifts"
+version = "0.1.0"
+
+[dependencies]
+absolute = {{ version = "1", package = "{FIXTURES}/day5_outside" }}
+relative = {{ version = "1", package = "../day5_outside" }}
+long = {{ version = "1", package = "{long}" }}
+"#,
+        long = "a".repeat(65),
+    ))
+    .await;
+
+    for name in ["absolute", "relative", "long"] {
+        assert_eq!(checks[name]["status"], json!("not-found"), "{name}");
+    }
+}
@@ -0,0 +1,85 @@
+use shuttlings_cch24::day5::policy::{spdx_allowed, Check, Policy, RustVersion};
+
+fn allowed(expression: &str) -> Option<bool> {
+    spdx_allowed(expression, &["MIT".to_string(), "Apache-2.0".to_string()])
+}
+
+#[test]
+fn spdx_expressions_are_evaluated() {
+    let cases = [
+        ("MIT", true),
+        ("GPL-3.0", false),
+        ("Apache-2.0+", true),
+        ("MIT OR GPL-3.0", true),
+        ("GPL-3.0 OR LGPL-2.1", false),
+        ("MIT AND Apache-2.0", true),
+        ("MIT AND GPL-3.0", false),
+        // AND binds tighter than OR
+        ("GPL-3.0 AND MIT OR Apache-2.0", true),
+        ("GPL-3.0 AND (MIT OR Apache-2.0)", false),
+        ("(MIT OR GPL-3.0) AND Apache-2.0", true),
+        ("((MIT))", true),
+        ("Apache-2.0 WITH LLVM-exception", true),
+        ("GPL-2.0 WITH Classpath-exception-2.0 OR MIT", true),
+        ("GPL-2.0 WITH Classpath-exception-2.0", false),
+        ("MIT/Apache-2.0", true),
+        ("GPL-3.0/LGPL-2.1", false),
+        ("GPL-3.0/MIT AND Apache-2.0", true),
+    ];
+
+    for (expression, expected) in cases {
+        assert_eq!(allowed(expression), Some(expected), "{expression}");
+    }
+}
+
+#[test]
+fn malformed_spdx_expressions_are_rejected() {
+    let cases = [
+        "",
+        "MIT OR",
+        "AND MIT",
+        "MIT Apache-2.0",
+        "(MIT",
+        "MIT)",
+        "()",
+        "MIT WITH",
+        "WITH LLVM-exception",
+        "MIT/",
+    ];
+
+    for expression in cases {
+        assert_eq!(allowed(expression), None, "{expression:?}");
+    }
+}
+
+#[test]
+fn min_rust_version_is_checked_when_the_policy_loads() {
+    let policy = |version: &str| {
+        toml::from_str::<Policy>(&format!(
+            r#"
+            [[rule]]
+            kind = "min-rust-version"
+            version = "{version}"
+            code = "rust-version-too-old"
+            message = "rust-version too old"
+            "#
+        ))
+    };
+
+    let rule = &policy("1.70").unwrap().rules[0];
+    assert!(matches!(
+        rule.check,
+        Check::MinRustVersion { version } if version.to_string() == "1.70"
+    ));
+    let version = |v: &str| RustVersion::try_from(v.to_string()).unwrap();
+    assert!(version("1.70.1") > version("1.70"));
+    assert!(version("1.9") < version("1.70"));
+
+    for version in ["1.x", "", "1.70.0.1", "v1.70"] {
+        let error = policy(version).unwrap_err();
+        assert!(
+            error.message().contains("is not a version like 1.70"),
+            "{version}: {error}"
+        );
+    }
+}
@@ -0,0 +1,412 @@
+use axum::{
+    body::{to_bytes, Body},
+    http::{Request, StatusCode},
+};
+use serde_json::{json, Value};
+use shuttlings_cch24::day5::{
+    day_05_routes,
+    workspace::{directory, resolve, Unresolved, Workspace},
+};
+use toml_edit::DocumentMut;
+use tower::ServiceExt;
+
+const ROOT: &str = r#"
+[workspace]
+members = ["crates/*"]
+
+[workspace.package]
+version = "1.2.3"
+edition = "2021"
+readme = "README.md"
+license-file = "LICENSES/MIT"
+
+[workspace.dependencies]
+serde = { version = "1", features = ["derive"] }
+anyhow = "1.0"
+local = { path = "crates/local" }
+
+[workspace.lints.rust]
+unsafe_code = "forbid"
+"#;
+
+const MEMBER: &str = r#"
+[package]
+name = "app"
+version.workspace = true
+edition = { workspace = true } # inherited
+readme.workspace = true
+license-file.workspace = true
+
+[dependencies]
+serde = { workspace = true, features = ["rc", "derive"], optional = true }
+anyhow.workspace = true
+local = { workspace = true }
+
+[target.'cfg(unix)'.dev-dependencies]
+anyhow = { workspace = true }
+
+[lints]
+workspace = true
+"#;
+
+fn root(file: Option<&str>) -> Workspace {
+    Workspace::from_root(&ROOT.parse().unwrap(), file).unwrap()
+}
+
+fn resolved(workspace: Option<&Workspace>, member: &str) -> (DocumentMut, Vec<Unresolved>) {
+    let mut doc: DocumentMut = MEMBER.parse().unwrap();
+    let unresolved = resolve(&mut doc, workspace, Some(&directory(member)));
+    (doc, unresolved)
+}
+
+fn toml(doc: &DocumentMut, path: &[&str]) -> String {
+    let item = path.iter().fold(doc.as_item(), |item, key| &item[key]);
+    item.to_string().trim().to_string()
+}
+
+#[test]
+fn members_inherit_package_fields() {
+    let (doc, unresolved) = resolved(Some(&root(Some("Cargo.toml"))), "crates/app/Cargo.toml");
+    assert!(unresolved.is_empty());
+
+    assert_eq!(doc["package"]["version"].as_str(), Some("1.2.3"));
+    assert_eq!(doc["package"]["name"].as_str(), Some("app"));
+    // The comment after the marker stays with the value
+    assert!(doc.to_string().contains("edition = \"2021\" # inherited\n"));
+}
+
+#[test]
+fn inherited_paths_are_rebased_onto_the_member() {
+    let (doc, _) = resolved(Some(&root(Some("Cargo.toml"))), "crates/app/Cargo.toml");
+    assert_eq!(doc["package"]["readme"].as_str(), Some("../../README.md"));
+    assert_eq!(
+        doc["package"]["license-file"].as_str(),
+        Some("../../LICENSES/MIT")
+    );
+    assert_eq!(
+        toml(&doc, &["dependencies", "local"]),
+        "{ path = \"../local\" }"
+    );
+
+    let (doc, _) = resolved(
+        Some(&root(Some("ws/Cargo.toml"))),
+        "ws/crates/app/Cargo.toml",
+    );
+    assert_eq!(doc["package"]["readme"].as_str(), Some("../../README.md"));
+
+    // Without the root's file name there is nothing to rebase against
+    let (doc, _) = resolved(Some(&root(None)), "crates/app/Cargo.toml");
+    assert_eq!(doc["package"]["readme"].as_str(), Some("README.md"));
+    assert_eq!(
+        toml(&doc, &["dependencies", "local"]),
+        "{ path = \"crates/local\" }"
+    );
+}
+
+#[test]
+fn dependencies_merge_with_the_member_declaration() {
+    let (doc, _) = resolved(Some(&root(Some("Cargo.toml"))), "crates/app/Cargo.toml");
+
+    // Member features are added to the inherited ones, without duplicates
+    assert_eq!(
+        toml(&doc, &["dependencies", "serde"]),
+        "{ version = \"1\", features = [\"derive\", \"rc\"], optional = true }"
+    );
+    // A bare version requirement becomes a table
+    assert_eq!(
+        toml(&doc, &["dependencies", "anyhow"]),
+        "{ version = \"1.0\" }"
+    );
+    assert_eq!(
+        toml(&doc, &["target", "cfg(unix)", "dev-dependencies", "anyhow"]),
+        "{ version = \"1.0\" }"
+    );
+}
+
+#[test]
+fn lints_are_replaced_by_the_workspace_lints() {
+    let (doc, _) = resolved(Some(&root(Some("Cargo.toml"))), "crates/app/Cargo.toml");
+    assert_eq!(doc["lints"]["rust"]["unsafe_code"].as_str(), Some("forbid"));
+    assert!(doc["lints"].get("workspace").is_none());
+}
+
+#[test]
+fn undefined_keys_are_reported_and_left_alone() {
+    let mut doc: DocumentMut = r#"
+[package]
+name = "app"
+description.workspace = true
+
+[dev-dependencies]
+missing = { workspace = true }
+"#
+    .parse()
+    .unwrap();
+    let before = doc.to_string();
+
+    let lintless = Workspace::from_root(&"[workspace]\n".parse().unwrap(), None).unwrap();
+    let unresolved = resolve(&mut doc, Some(&lintless), None);
+    let unresolved = unresolved
+        .iter()
+        .map(|u| (u.path.join("."), u.reason.as_str()))
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        unresolved,
+        [
+            (
+                "package.description".to_string(),
+                "workspace.package.description is not defined"
+            ),
+            (
+                "dev-dependencies.missing".to_string(),
+                "workspace.dependencies.missing is not defined"
+            ),
+        ]
+    );
+    assert_eq!(doc.to_string(), before);
+}
+
+#[test]
+fn markers_without_a_root_are_reported() {
+    assert!(Workspace::from_root(&MEMBER.parse().unwrap(), None).is_none());
+
+    let (doc, unresolved) = resolved(None, "crates/app/Cargo.toml");
+    let paths = unresolved
+        .iter()
+        .map(|u| u.path.join("."))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        paths,
+        [
+            "package.version",
+            "package.edition",
+            "package.readme",
+            "package.license-file",
+            "dependencies.serde",
+            "dependencies.anyhow",
+            "dependencies.local",
+            "target.cfg(unix).dev-dependencies.anyhow",
+            "lints",
+        ]
+    );
+    assert!(unresolved[..8]
+        .iter()
+        .all(|u| u.reason == "inherits from a workspace, but no workspace root was uploaded"));
+    assert_eq!(unresolved[8].reason, "workspace.lints is not defined");
+    assert_eq!(doc.to_string(), MEMBER);
+}
+
+/// One part of a workspace upload: its field name, file name, content type
+/// and data.
+type Part<'a> = (&'a str, Option<&'a str>, Option<&'a str>, &'a str);
+
+async fn upload(accept: Option<&str>, parts: &[Part<'_>]) -> (StatusCode, String) {
+    let mut body = String::new();
+    for (name, file, content_type, data) in parts {
+        body.push_str("--gift-wrap\r\n");
+        body.push_str(&format!("Content-Disposition: form-data; name=\"{name}\""));
+        if let Some(file) = file {
+            body.push_str(&format!("; filename=\"{file}\""));
+        }
+        body.push_str("\r\n");
+        if let Some(content_type) = content_type {
+            body.push_str(&format!("Content-Type: {content_type}\r\n"));
+        }
+        body.push_str(&format!("\r\n{data}\r\n"));
+    }
+    body.push_str("--gift-wrap--\r\n");
+
+    let mut request = Request::post("/manifest")
+        .header("content-type", "multipart/form-data; boundary=gift-wrap");
+    if let Some(accept) = accept {
+        request = request.header("accept", accept);
+    }
+    let request = request.body(Body::from(body)).unwrap();
+
+    let response = day_05_routes().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+/// The JSON report for `parts`, with each problem as its file and code.
+async fn problems(parts: &[Part<'_>]) -> (StatusCode, Vec<(String, String)>, Value) {
+    let (status, body) = upload(Some("application/json"), parts).await;
+    let report: Value = serde_json::from_str(&body).unwrap();
+    let problems = report["problems"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|p| {
+            (
+                p["file"].as_str().unwrap().to_string(),
+                p["code"].as_str().unwrap().to_string(),
+            )
+        })
+        .collect();
+    (status, problems, report)
+}
+
+fn codes(problems: &[(&str, &str)]) -> Vec<(String, String)> {
+    problems
+        .iter()
+        .map(|(file, code)| (file.to_string(), code.to_string()))
+        .collect()
+}
+
+const UPLOADED_ROOT: &str = r#"
+[workspace]
+members = ["crates/*"]
+
+[workspace.package]
+version = "1.2.3"
+keywords = ["Christmas 2024"]
+"#;
+
+const TOYS: &str = r#"
+[package]
+name = "toys"
+version.workspace = true
+keywords.workspace = true
+
+[[package.metadata.orders]]
+item = "Toy car"
+quantity = 2
+"#;
+
+const SWEETS: &str = r#"{
+  "package": {
+    "name": "sweets",
+    "version": { "workspace": true },
+    "keywords": { "workspace": true },
+    "metadata": { "orders": [{ "item": "Candy cane", "quantity": 3 }] }
+  }
+}"#;
+
+#[tokio::test]
+async fn uploaded_members_inherit_from_the_uploaded_root() {
+    let parts = [
+        ("root", Some("Cargo.toml"), None, UPLOADED_ROOT),
+        ("member", Some("crates/toys/Cargo.toml"), None, TOYS),
+        (
+            "member",
+            Some("crates/sweets/Cargo.json"),
+            Some("application/json"),
+            SWEETS,
+        ),
+    ];
+
+    assert_eq!(
+        upload(None, &parts).await,
+        (StatusCode::OK, "Toy car: 2\nCandy cane: 3".to_string())
+    );
+    let (status, problems, report) = self::problems(&parts).await;
+    assert_eq!((status, problems), (StatusCode::OK, vec![]));
+    assert_eq!(report["orders"][1]["item"], "Candy cane");
+}
+
+#[tokio::test]
+async fn a_root_without_a_workspace_table_is_reported() {
+    let parts = [
+        (
+            "root",
+            Some("Cargo.toml"),
+            None,
+            "[package]\nname = \"x\"\n",
+        ),
+        ("member", Some("crates/toys/Cargo.toml"), None, TOYS),
+    ];
+
+    let (status, problems, report) = self::problems(&parts).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        problems,
+        codes(&[
+            ("Cargo.toml", "missing-workspace"),
+            ("Cargo.toml", "magic-keyword-missing"),
+            ("crates/toys/Cargo.toml", "unresolved-inheritance"),
+            ("crates/toys/Cargo.toml", "unresolved-inheritance"),
+            ("crates/toys/Cargo.toml", "magic-keyword-missing"),
+        ])
+    );
+    assert_eq!(
+        report["problems"][0]["detail"],
+        "root manifest has no [workspace] table"
+    );
+    assert_eq!(
+        (
+            &report["problems"][2]["path"],
+            &report["problems"][2]["line"]
+        ),
+        (&json!("package.version"), &json!(4))
+    );
+
+    assert_eq!(
+        upload(None, &parts).await,
+        (StatusCode::BAD_REQUEST, "Invalid manifest".to_string())
+    );
+}
+
+#[tokio::test]
+async fn a_root_with_a_package_is_validated_as_a_member_too() {
+    let root = format!(
+        "{UPLOADED_ROOT}\n[package]\nname = \"workshop\"\nversion.workspace = true\n\n\
+         [[package.metadata.orders]]\nitem = \"Sleigh\"\nquantity = 1\n"
+    );
+    let parts = [
+        ("root", Some("Cargo.toml"), None, root.as_str()),
+        ("member", Some("crates/toys/Cargo.toml"), None, TOYS),
+    ];
+
+    // The root's own package inherits from its workspace, and misses the
+    // keyword by not asking for it
+    let (status, problems, report) = self::problems(&parts).await;
+    assert_eq!(
+        (status, problems),
+        (
+            StatusCode::BAD_REQUEST,
+            codes(&[("Cargo.toml", "magic-keyword-missing")])
+        )
+    );
+    let items = report["orders"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|o| o["item"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(items, ["Sleigh", "Toy car"]);
+}
+
+#[tokio::test]
+async fn each_part_is_read_in_its_own_format() {
+    let yaml = "package:\n  name: ornaments\n  version:\n    workspace: true\n  keywords:\n    workspace: true\n";
+    let parts = [
+        ("root", None, Some("application/toml"), UPLOADED_ROOT),
+        ("ornaments", None, Some("application/yaml"), yaml),
+        ("sweets", None, Some("application/json"), "{ \"package\": "),
+    ];
+
+    // Parts without a file name are reported by their field name
+    let (status, problems, report) = self::problems(&parts).await;
+    assert_eq!(
+        (status, problems),
+        (
+            StatusCode::BAD_REQUEST,
+            codes(&[("sweets", "invalid-syntax")])
+        )
+    );
+    assert_eq!(report["problems"][0]["line"], 1);
+
+    let parts = [
+        ("root", None, None, UPLOADED_ROOT),
+        ("member", None, Some("text/html"), TOYS),
+    ];
+    assert_eq!(
+        upload(Some("application/json"), &parts).await,
+        (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Unknown content type".to_string()
+        )
+    );
+}
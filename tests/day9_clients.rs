@@ -0,0 +1,145 @@
+use std::net::{IpAddr, SocketAddr};
+
+use axum::http::HeaderMap;
+use jsonwebtoken::{encode, Algorithm, DecodingKey, EncodingKey, Header};
+use serde_json::json;
+use shuttlings_cch24::day9::{
+    clients::{client_ip, ClientKey},
+    config::Limits,
+};
+
+const PRIVATE_KEY: &[u8] = include_bytes!("fixtures/day9_jwt_private.pem");
+const PUBLIC_KEY: &[u8] = include_bytes!("fixtures/day9_jwt_public.pem");
+
+fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    for (name, value) in pairs {
+        headers.append(*name, value.parse().unwrap());
+    }
+    headers
+}
+
+fn ip(ip: &str) -> Option<IpAddr> {
+    Some(ip.parse().unwrap())
+}
+
+#[test]
+fn forwarded_for_is_read_from_the_right() {
+    let peer = Some(SocketAddr::from(([10, 0, 0, 1], 443)));
+    let forwarded = headers(&[
+        ("x-forwarded-for", "6.6.6.6, 1.2.3.4"),
+        ("x-forwarded-for", "172.16.0.9"),
+    ]);
+
+    assert_eq!(client_ip(&forwarded, peer, 0), ip("10.0.0.1"));
+    assert_eq!(client_ip(&forwarded, peer, 1), ip("172.16.0.9"));
+    assert_eq!(client_ip(&forwarded, peer, 2), ip("1.2.3.4"));
+    // Fewer hops than proxies, or no header at all: the peer it is
+    assert_eq!(client_ip(&forwarded, peer, 4), ip("10.0.0.1"));
+    assert_eq!(client_ip(&HeaderMap::new(), peer, 1), ip("10.0.0.1"));
+    assert_eq!(
+        client_ip(&headers(&[("x-forwarded-for", "garbage")]), peer, 1),
+        ip("10.0.0.1")
+    );
+    assert_eq!(client_ip(&HeaderMap::new(), None, 0), None);
+}
+
+#[test]
+fn only_known_api_keys_get_a_bucket() {
+    let limits: Limits = toml::from_str(
+        r#"
+        [clients."key:load-test"]
+        algorithm = "fixed-window"
+        limit = 100
+        window-ms = 60000
+        "#,
+    )
+    .unwrap();
+    let identify = |key: &str| {
+        ClientKey::ApiKey.identify(&headers(&[("x-api-key", key)]), ip("1.2.3.4"), &limits)
+    };
+
+    assert_eq!(identify("load-test"), "key:load-test");
+    assert_eq!(identify("made-up"), "ip:1.2.3.4");
+    assert_eq!(
+        ClientKey::ApiKey.identify(&HeaderMap::new(), None, &limits),
+        "ip:unknown"
+    );
+}
+
+#[test]
+fn ipv6_clients_are_keyed_by_their_64() {
+    let identify =
+        |addr: &str| ClientKey::PeerIp.identify(&HeaderMap::new(), ip(addr), &Limits::default());
+
+    assert_eq!(identify("2001:db8:1:2::1"), "ip:2001:db8:1:2::/64");
+    assert_eq!(identify("2001:db8:1:2:ffff:1:2:3"), "ip:2001:db8:1:2::/64");
+    assert_eq!(identify("2001:db8:1:3::1"), "ip:2001:db8:1:3::/64");
+    // IPv4 clients of a dual-stack socket are still keyed by their address
+    assert_eq!(identify("::ffff:1.2.3.4"), "ip:1.2.3.4");
+    assert_eq!(identify("1.2.3.4"), "ip:1.2.3.4");
+}
+
+#[test]
+fn jwt_subjects_need_a_valid_signature() {
+    let key = ClientKey::jwt_subject(
+        DecodingKey::from_rsa_pem(PUBLIC_KEY).unwrap(),
+        &[Algorithm::RS256],
+    );
+    let limits = Limits::default();
+    let token = |claims: serde_json::Value, signer: &EncodingKey| {
+        encode(&Header::new(Algorithm::RS256), &claims, signer).unwrap()
+    };
+    let later = jsonwebtoken::get_current_timestamp() + 3600;
+    let identify = |token: &str| {
+        let bearer = format!("Bearer {token}");
+        key.identify(
+            &headers(&[("authorization", &bearer)]),
+            ip("1.2.3.4"),
+            &limits,
+        )
+    };
+
+    let signer = EncodingKey::from_rsa_pem(PRIVATE_KEY).unwrap();
+    assert_eq!(
+        identify(&token(json!({ "sub": "elf", "exp": later }), &signer)),
+        "sub:elf"
+    );
+
+    let expired = json!({ "sub": "elf", "exp": 1_000_000_000 });
+    assert_eq!(identify(&token(expired, &signer)), "ip:1.2.3.4");
+    // A token that never expires isn't one to trust
+    assert_eq!(
+        identify(&token(json!({ "sub": "elf" }), &signer)),
+        "ip:1.2.3.4"
+    );
+    // Nor is one signed with an algorithm other than the pinned one, even
+    // with the right key
+    let ps256 = encode(
+        &Header::new(Algorithm::PS256),
+        &json!({ "sub": "elf", "exp": later }),
+        &signer,
+    )
+    .unwrap();
+    assert_eq!(identify(&ps256), "ip:1.2.3.4");
+
+    let forged = encode(
+        &Header::default(),
+        &json!({ "sub": "santa", "exp": later }),
+        &EncodingKey::from_secret(b"guess"),
+    )
+    .unwrap();
+    assert_eq!(identify(&forged), "ip:1.2.3.4");
+
+    // A valid signature over a different payload
+    let genuine = token(json!({ "sub": "elf", "exp": later }), &signer);
+    let [header, _, signature] = genuine.split('.').collect::<Vec<_>>()[..] else {
+        unreachable!()
+    };
+    let payload = token(json!({ "sub": "santa", "exp": later }), &signer);
+    let tampered = format!(
+        "{header}.{}.{signature}",
+        payload.split('.').nth(1).unwrap()
+    );
+    assert_eq!(identify(&tampered), "ip:1.2.3.4");
+}
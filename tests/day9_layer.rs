@@ -0,0 +1,375 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    body::Body,
+    http::{HeaderMap, Method, Request, StatusCode},
+    response::Response,
+    routing::{any, post},
+    Router,
+};
+use shuttlings_cch24::{
+    day19::day_19_routes,
+    day9::{
+        clients::{ClientKey, Clients},
+        day_9_routes,
+        layer::RateLimit,
+        limiter::Clock,
+    },
+};
+use tokio::{task::yield_now, time::Instant};
+use tower::ServiceExt;
+
+/// The limiter's clock, following tokio's so that pausing time stops it.
+struct TokioClock;
+
+impl Clock for TokioClock {
+    fn now(&self) -> std::time::Instant {
+        Instant::now().into_std()
+    }
+}
+
+/// A route that allows one request per `interval_ms`.
+fn app(interval_ms: u64) -> Router {
+    let limits = toml::from_str(&format!(
+        r#"
+        [default]
+        algorithm = "token-bucket"
+        capacity = 1
+        refill = 1
+        interval-ms = {interval_ms}
+        "#
+    ))
+    .unwrap();
+    let limit = RateLimit::new(
+        ClientKey::PeerIp,
+        Clients::new(limits, Arc::new(TokioClock)),
+    );
+
+    Router::new()
+        .route("/milk", post(|| async { "Milk withdrawn\n" }))
+        .layer(limit.layer("milk").all())
+}
+
+/// Sends a request that waits up to `wait` seconds, and says how it went
+/// and when it was answered.
+async fn milk(app: &Router, wait: Option<u64>) -> (StatusCode, Instant) {
+    let mut request = Request::post("/milk");
+    if let Some(wait) = wait {
+        request = request.header("prefer", format!("wait={wait}"));
+    }
+    let request = request.body(Body::empty()).unwrap();
+
+    let response = app.clone().oneshot(request).await.unwrap();
+    (response.status(), Instant::now())
+}
+
+/// Whole seconds from `start` to `at`. Timers fire on the millisecond
+/// after they are due.
+fn seconds(start: Instant, at: Instant) -> u64 {
+    (at - start).as_secs_f64().round() as u64
+}
+
+/// Starts a waiting request and lets it join the queue.
+async fn waiter(app: &Router, wait: u64) -> tokio::task::JoinHandle<(StatusCode, Instant)> {
+    let app = app.clone();
+    let handle = tokio::spawn(async move { milk(&app, Some(wait)).await });
+    yield_now().await;
+    handle
+}
+
+#[tokio::test]
+async fn waiters_are_served_in_order() {
+    tokio::time::pause();
+    let app = app(1000);
+    let start = Instant::now();
+    assert_eq!(milk(&app, None).await.0, StatusCode::OK);
+
+    let first = waiter(&app, 5).await;
+    let second = waiter(&app, 5).await;
+    // Someone who doesn't wait doesn't jump the queue either
+    assert_eq!(milk(&app, None).await.0, StatusCode::TOO_MANY_REQUESTS);
+
+    let (status, at) = first.await.unwrap();
+    assert_eq!((status, seconds(start, at)), (StatusCode::OK, 1));
+    let (status, at) = second.await.unwrap();
+    assert_eq!((status, seconds(start, at)), (StatusCode::OK, 2));
+}
+
+#[tokio::test]
+async fn waiters_time_out_with_429() {
+    tokio::time::pause();
+    let app = app(10_000);
+    let start = Instant::now();
+    assert_eq!(milk(&app, None).await.0, StatusCode::OK);
+
+    let request = Request::post("/milk")
+        .header("prefer", "wait=2")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers()["preference-applied"], "wait=2");
+    assert_eq!(seconds(start, Instant::now()), 2);
+}
+
+#[tokio::test]
+async fn dropped_waiters_leave_the_queue() {
+    tokio::time::pause();
+    let app = app(1000);
+    let start = Instant::now();
+    assert_eq!(milk(&app, None).await.0, StatusCode::OK);
+
+    let first = waiter(&app, 30).await;
+    let second = waiter(&app, 30).await;
+    first.abort();
+
+    // The token that would have been the first waiter's goes to the second
+    let (status, at) = second.await.unwrap();
+    assert_eq!((status, seconds(start, at)), (StatusCode::OK, 1));
+}
+
+/// A rate limit with `limits` as its limits file.
+fn rate_limit(limits: &str) -> RateLimit {
+    RateLimit::new(
+        ClientKey::PeerIp,
+        Clients::new(toml::from_str(limits).unwrap(), Arc::new(TokioClock)),
+    )
+}
+
+async fn send(app: &Router, method: Method, uri: &str) -> Response {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .body(Body::empty())
+        .unwrap();
+    app.clone().oneshot(request).await.unwrap()
+}
+
+/// The status of each of `requests`, sent one after the other.
+async fn statuses(app: &Router, requests: &[(Method, &str)]) -> Vec<u16> {
+    let mut statuses = vec![];
+    for (method, uri) in requests {
+        statuses.push(send(app, method.clone(), uri).await.status().as_u16());
+    }
+    statuses
+}
+
+const ONE_A_MINUTE: &str = r#"
+    [default]
+    algorithm = "token-bucket"
+    capacity = 1
+    refill = 1
+    interval-ms = 60000
+"#;
+
+#[tokio::test]
+async fn routes_have_quotas_of_their_own() {
+    tokio::time::pause();
+    let limits = format!(
+        r#"{ONE_A_MINUTE}
+        [routes."gifts POST /wrap"]
+        algorithm = "token-bucket"
+        capacity = 2
+        refill = 1
+        interval-ms = 60000
+        "#
+    );
+    let limit = rate_limit(&limits);
+    let app = Router::new()
+        .route("/wrap", any(|| async { "Wrapped\n" }))
+        .route("/tag/:id", any(|| async { "Tagged\n" }))
+        .route("/tag/:id/note", any(|| async { "Noted\n" }))
+        .route("/files/*rest", any(|| async { "Filed\n" }))
+        .layer(
+            limit
+                .layer("gifts")
+                .route(Method::POST, "/wrap")
+                .route(Method::PUT, "/tag/:id")
+                .route(Method::GET, "/files/*rest"),
+        );
+
+    // Its own limit from the limits file
+    let response = send(&app, Method::POST, "/wrap").await;
+    assert_eq!(response.headers()["ratelimit-limit"], "2");
+    assert_eq!(
+        statuses(&app, &[(Method::POST, "/wrap"), (Method::POST, "/wrap")]).await,
+        [200, 429]
+    );
+    // The client's limit, in a bucket apart from /wrap's, whatever the id
+    assert_eq!(
+        statuses(&app, &[(Method::PUT, "/tag/1"), (Method::PUT, "/tag/2")]).await,
+        [200, 429]
+    );
+    assert_eq!(
+        statuses(
+            &app,
+            &[(Method::GET, "/files/a/b.txt"), (Method::GET, "/files/c")]
+        )
+        .await,
+        [200, 429]
+    );
+
+    // Other methods, and paths with more or fewer segments, aren't limited
+    for (method, uri) in [
+        (Method::GET, "/wrap"),
+        (Method::PUT, "/tag/1/note"),
+        (Method::PUT, "/tag/"),
+    ] {
+        let response = send(&app, method.clone(), uri).await;
+        assert_ne!(
+            response.status(),
+            StatusCode::TOO_MANY_REQUESTS,
+            "{method} {uri}"
+        );
+        assert!(
+            !response.headers().contains_key("ratelimit-limit"),
+            "{method} {uri}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn layers_from_one_rate_limit_share_its_buckets() {
+    tokio::time::pause();
+    let limit = rate_limit(ONE_A_MINUTE);
+    let router = |layer: &str| {
+        Router::new()
+            .route("/milk", post(|| async { "Milk withdrawn\n" }))
+            .layer(limit.layer(layer).all())
+    };
+    let (first, second, other) = (router("milk"), router("milk"), router("cookies"));
+
+    // Layers of the same name draw from the same buckets, wherever they are
+    assert_eq!(milk(&first, None).await.0, StatusCode::OK);
+    assert_eq!(milk(&second, None).await.0, StatusCode::TOO_MANY_REQUESTS);
+    // Other layers have buckets of their own
+    assert_eq!(milk(&other, None).await.0, StatusCode::OK);
+
+    // Refilling through one layer refills the bucket for all of them
+    limit.layer("milk").refill(&HeaderMap::new(), None).await;
+    assert_eq!(milk(&second, None).await.0, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn quote_writes_are_limited_per_route() {
+    tokio::time::pause();
+    let limits = format!(
+        r#"{ONE_A_MINUTE}
+        [routes."quotes PUT /undo/:id"]
+        algorithm = "token-bucket"
+        capacity = 2
+        refill = 1
+        interval-ms = 60000
+        "#
+    );
+    let limit = rate_limit(&limits);
+    // Requests that are turned away, or fail to parse, never reach the
+    // database
+    let pool = sqlx::PgPool::connect_lazy("postgres://nobody@localhost:1/none").unwrap();
+    let app = Router::new().nest("/19", day_19_routes(pool, &limit));
+    let id = "not-a-uuid";
+
+    // Limited before the handler looks at the body or the id
+    assert_eq!(
+        statuses(
+            &app,
+            &[(Method::POST, "/19/draft"), (Method::POST, "/19/draft")]
+        )
+        .await,
+        [415, 429]
+    );
+    assert_eq!(
+        statuses(
+            &app,
+            &[
+                (Method::PUT, &format!("/19/undo/{id}")),
+                (Method::PUT, "/19/undo/another"),
+                (Method::PUT, &format!("/19/undo/{id}")),
+            ]
+        )
+        .await,
+        [400, 400, 429]
+    );
+    assert_eq!(
+        statuses(
+            &app,
+            &[
+                (Method::DELETE, &format!("/19/remove/{id}")),
+                (Method::DELETE, &format!("/19/remove/{id}")),
+            ]
+        )
+        .await,
+        [400, 429]
+    );
+
+    // Reads aren't limited
+    for _ in 0..3 {
+        let response = send(&app, Method::GET, &format!("/19/cite/{id}")).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(!response.headers().contains_key("ratelimit-limit"));
+    }
+}
+
+#[tokio::test]
+async fn milk_responses_carry_the_quota() {
+    tokio::time::pause();
+    let limit = rate_limit(
+        r#"
+        [default]
+        algorithm = "token-bucket"
+        capacity = 2
+        refill = 1
+        interval-ms = 10000
+        "#,
+    );
+    let app = Router::new().nest("/9", day_9_routes(limit));
+    let quota = |response: &Response| {
+        let header = |name: &str| response.headers()[name].to_str().unwrap().to_string();
+        (
+            response.status(),
+            header("ratelimit-limit"),
+            header("ratelimit-remaining"),
+            header("ratelimit-reset"),
+            header("retry-after"),
+        )
+    };
+    let expect = |status, limit: &str, remaining: &str, reset: &str, retry: &str| {
+        (
+            status,
+            limit.to_string(),
+            remaining.to_string(),
+            reset.to_string(),
+            retry.to_string(),
+        )
+    };
+
+    let response = send(&app, Method::POST, "/9/milk").await;
+    assert_eq!(
+        quota(&response),
+        expect(StatusCode::OK, "2", "1", "10", "0")
+    );
+    let response = send(&app, Method::POST, "/9/milk").await;
+    assert_eq!(
+        quota(&response),
+        expect(StatusCode::OK, "2", "0", "20", "10")
+    );
+    let response = send(&app, Method::POST, "/9/milk").await;
+    assert_eq!(
+        quota(&response),
+        expect(StatusCode::TOO_MANY_REQUESTS, "2", "0", "20", "10")
+    );
+
+    // Part way through, the reset and retry count down, rounded up
+    tokio::time::advance(Duration::from_millis(2500)).await;
+    let response = send(&app, Method::POST, "/9/milk").await;
+    assert_eq!(
+        quota(&response),
+        expect(StatusCode::TOO_MANY_REQUESTS, "2", "0", "18", "8")
+    );
+    tokio::time::advance(Duration::from_millis(7500)).await;
+    let response = send(&app, Method::POST, "/9/milk").await;
+    assert_eq!(
+        quota(&response),
+        expect(StatusCode::OK, "2", "0", "20", "10")
+    );
+}
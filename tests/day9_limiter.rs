@@ -0,0 +1,276 @@
+use std::{sync::Arc, time::Duration};
+
+use shuttlings_cch24::day9::{
+    clients::{Clients, MAX_CLIENTS},
+    config::{LimiterConfig, Limits},
+    limiter::{MockClock, Quota, Timeline},
+};
+
+fn secs(secs: f64) -> Duration {
+    Duration::from_secs_f64(secs)
+}
+
+fn limits(config: &str) -> Limits {
+    toml::from_str(config).unwrap()
+}
+
+/// Draws `n` times from `client`, returning which draws were allowed.
+fn draw(clients: &mut Clients, client: &str, n: usize) -> Vec<bool> {
+    (0..n)
+        .map(|_| clients.try_acquire(client).allowed)
+        .collect()
+}
+
+fn setup(config: &str) -> (Clients, MockClock) {
+    let clock = MockClock::default();
+    let clients = Clients::new(limits(config), Arc::new(clock.clone()));
+    (clients, clock)
+}
+
+#[test]
+fn token_bucket_refills_one_per_interval() {
+    let (mut clients, clock) = setup("");
+
+    assert_eq!(
+        draw(&mut clients, "a", 6),
+        [true, true, true, true, true, false]
+    );
+    assert_eq!(
+        clients.try_acquire("a"),
+        Quota {
+            allowed: false,
+            limit: 5,
+            remaining: 0,
+            reset: secs(5.0),
+            retry_after: secs(1.0),
+        }
+    );
+
+    clock.advance(secs(0.5));
+    assert_eq!(clients.try_acquire("a").retry_after, secs(0.5));
+    clock.advance(secs(0.5));
+    assert_eq!(draw(&mut clients, "a", 2), [true, false]);
+
+    clock.advance(secs(10.0));
+    let quota = clients.try_acquire("a");
+    assert_eq!((quota.remaining, quota.reset), (4, secs(1.0)));
+}
+
+#[test]
+fn fixed_window_resets_at_the_boundary() {
+    let (mut clients, clock) = setup(
+        r#"
+        [default]
+        algorithm = "fixed-window"
+        limit = 3
+        window-ms = 10000
+        "#,
+    );
+
+    // The window starts with the first draw
+    assert!(clients.try_acquire("a").allowed);
+    clock.advance(secs(9.0));
+    assert_eq!(draw(&mut clients, "a", 3), [true, true, false]);
+    assert_eq!(clients.try_acquire("a").retry_after, secs(1.0));
+
+    // A full allowance again straight after the boundary
+    clock.advance(secs(1.0));
+    assert_eq!(draw(&mut clients, "a", 4), [true, true, true, false]);
+    assert_eq!(clients.try_acquire("a").reset, secs(10.0));
+}
+
+#[test]
+fn sliding_window_log_counts_the_last_window() {
+    let (mut clients, clock) = setup(
+        r#"
+        [default]
+        algorithm = "sliding-window-log"
+        limit = 3
+        window-ms = 10000
+        "#,
+    );
+
+    for _ in 0..3 {
+        assert!(clients.try_acquire("a").allowed);
+        clock.advance(secs(4.0));
+    }
+    // Now at 12s: the draw at 0s has expired, the ones at 4s and 8s haven't
+    let quota = clients.try_acquire("a");
+    assert_eq!((quota.allowed, quota.remaining), (true, 0));
+    assert_eq!(quota.retry_after, secs(2.0));
+    assert_eq!(quota.reset, secs(10.0));
+
+    clock.advance(secs(1.0));
+    assert!(!clients.try_acquire("a").allowed);
+    clock.advance(secs(1.0));
+    assert!(clients.try_acquire("a").allowed);
+}
+
+#[test]
+fn gcra_allows_a_burst_then_spaces_out() {
+    let (mut clients, clock) = setup(
+        r#"
+        [default]
+        algorithm = "gcra"
+        burst = 3
+        interval-ms = 1000
+        "#,
+    );
+
+    assert_eq!(draw(&mut clients, "a", 4), [true, true, true, false]);
+    let quota = clients.try_acquire("a");
+    assert_eq!((quota.remaining, quota.reset), (0, secs(3.0)));
+    assert_eq!(quota.retry_after, secs(1.0));
+
+    clock.advance(secs(0.5));
+    assert_eq!(clients.try_acquire("a").retry_after, secs(0.5));
+    clock.advance(secs(0.5));
+    assert_eq!(draw(&mut clients, "a", 2), [true, false]);
+
+    clock.advance(secs(3.0));
+    assert_eq!(clients.try_acquire("a").remaining, 2);
+}
+
+#[test]
+fn clients_get_their_own_limiters() {
+    let (mut clients, _) = setup(
+        r#"
+        [clients."key:burst"]
+        algorithm = "gcra"
+        burst = 8
+        interval-ms = 1000
+        "#,
+    );
+
+    assert_eq!(
+        draw(&mut clients, "key:a", 6)
+            .iter()
+            .filter(|a| **a)
+            .count(),
+        5
+    );
+    assert_eq!(
+        draw(&mut clients, "key:b", 6)
+            .iter()
+            .filter(|a| **a)
+            .count(),
+        5
+    );
+    assert_eq!(clients.try_acquire("key:burst").limit, 8);
+}
+
+#[test]
+fn idle_clients_are_evicted() {
+    let (mut clients, clock) = setup(
+        r#"
+        [default]
+        algorithm = "token-bucket"
+        capacity = 2
+        refill = 1
+        interval-ms = 10000
+        "#,
+    );
+
+    draw(&mut clients, "a", 2);
+    draw(&mut clients, "b", 1);
+    assert_eq!(clients.len(), 2);
+
+    // b has refilled and goes, a is still one short
+    clock.advance(secs(10.0));
+    clients.try_acquire("c");
+    assert_eq!(clients.len(), 2);
+
+    clock.advance(secs(10.0));
+    clients.try_acquire("d");
+    assert_eq!(clients.len(), 1);
+}
+
+#[test]
+fn bad_limits_are_rejected() {
+    assert!(
+        toml::from_str::<LimiterConfig>("algorithm = \"gcra\"\nburst = 0\ninterval-ms = 1")
+            .is_err()
+    );
+    assert!(toml::from_str::<LimiterConfig>("algorithm = \"leaky\"").is_err());
+    assert!(
+        toml::from_str::<Limits>("[default]\nalgorithm = \"fixed-window\"\nlimit = 1").is_err()
+    );
+}
+
+#[test]
+fn queued_requests_are_served_in_order() {
+    let (mut clients, clock) = setup("");
+    let config = LimiterConfig::default();
+
+    draw(&mut clients, "a", 5);
+    let (first, _) = clients.enqueue("a", &config);
+    let (second, _) = clients.enqueue("a", &config);
+
+    clock.advance(secs(1.0));
+    // A token is back, but the queue goes first
+    assert!(!clients.try_acquire("a").allowed);
+    let (quota, at_front) = clients.try_acquire_queued("a", &config, second);
+    assert!(!quota.allowed && !at_front);
+    let (quota, at_front) = clients.try_acquire_queued("a", &config, first);
+    assert!(quota.allowed && at_front);
+
+    // Leaving the queue lets the next one through
+    let (third, _) = clients.enqueue("a", &config);
+    clock.advance(secs(1.0));
+    clients.dequeue("a", second);
+    let (quota, at_front) = clients.try_acquire_queued("a", &config, third);
+    assert!(quota.allowed && at_front);
+    assert!(!clients.try_acquire("a").allowed);
+}
+
+#[test]
+fn snapshots_carry_state_between_processes() {
+    let configs = [
+        "algorithm = \"token-bucket\"\ncapacity = 3\nrefill = 1\ninterval-ms = 1000",
+        "algorithm = \"fixed-window\"\nlimit = 3\nwindow-ms = 1000",
+        "algorithm = \"sliding-window-log\"\nlimit = 3\nwindow-ms = 1000",
+        "algorithm = \"gcra\"\nburst = 3\ninterval-ms = 1000",
+    ];
+    for config in configs {
+        let config = toml::from_str::<LimiterConfig>(config).unwrap();
+        let here = Timeline::new(1_700_000_000_000_000);
+        let mut limiter = config.build(here.now);
+        for at in [0, 200, 400, 600] {
+            limiter.try_acquire(here.now + Duration::from_millis(at));
+        }
+        let snapshot = limiter.snapshot(&here);
+
+        // Another process picks the bucket up 900ms in
+        let there = Timeline::new(1_700_000_000_900_000);
+        let mut restored = config.build(there.now);
+        restored.restore(&snapshot, &there);
+        for after in [0, 100, 300, 1000] {
+            let later = Duration::from_millis(after);
+            assert_eq!(
+                restored.peek(there.now + later),
+                limiter.peek(here.now + Duration::from_millis(900) + later),
+                "{config:?} {after}ms later"
+            );
+        }
+    }
+}
+
+#[test]
+fn clients_past_capacity_share_the_overflow_bucket() {
+    let (mut clients, clock) = setup("");
+    for client in 0..MAX_CLIENTS {
+        clients.try_acquire(&client.to_string());
+    }
+    assert_eq!(clients.len(), MAX_CLIENTS);
+
+    // Nobody is evicted to make room, so "0" still has 3 of 5 left
+    assert_eq!(draw(&mut clients, "a", 3), [true, true, true]);
+    assert_eq!(draw(&mut clients, "b", 3), [true, true, false]);
+    assert_eq!(clients.len(), MAX_CLIENTS + 1);
+    assert_eq!(clients.try_acquire("0").remaining, 3);
+
+    // Once buckets go idle there is room again
+    clock.advance(secs(10.0));
+    assert_eq!(draw(&mut clients, "b", 5), [true; 5]);
+    assert_eq!(clients.len(), 1);
+}
@@ -0,0 +1,154 @@
+//! These need a database, and are skipped unless `DATABASE_URL` is set.
+
+use std::{env, sync::Arc, time::Duration};
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    routing::post,
+    Router,
+};
+use shuttlings_cch24::day9::{
+    clients::{ClientKey, Clients},
+    config::LimiterConfig,
+    layer::RateLimit,
+    limiter::SystemClock,
+    store::PgBuckets,
+};
+use sqlx::PgPool;
+use tower::ServiceExt;
+use uuid::Uuid;
+
+async fn pool() -> Option<PgPool> {
+    let Ok(url) = env::var("DATABASE_URL") else {
+        eprintln!("DATABASE_URL is not set, skipping");
+        return None;
+    };
+    let pool = PgPool::connect(&url).await.unwrap();
+    sqlx::migrate!().run(&pool).await.unwrap();
+    Some(pool)
+}
+
+/// A bucket name no other test run uses.
+fn bucket() -> String {
+    format!("test {}", Uuid::new_v4())
+}
+
+fn config(limits: &str) -> LimiterConfig {
+    toml::from_str(limits).unwrap()
+}
+
+#[tokio::test]
+async fn buckets_are_stored_between_draws() {
+    let Some(pool) = pool().await else {
+        return;
+    };
+    let store = PgBuckets::new(pool.clone());
+    let bucket = bucket();
+    let config = config(
+        r#"
+        algorithm = "token-bucket"
+        capacity = 2
+        refill = 1
+        interval-ms = 60000
+        "#,
+    );
+
+    let draw = || async { store.try_acquire(&bucket, &config).await.unwrap() };
+    let quotas = [draw().await, draw().await, draw().await];
+    assert_eq!(
+        quotas.map(|q| (q.allowed, q.remaining)),
+        [(true, 1), (true, 0), (false, 0)]
+    );
+
+    let state: serde_json::Value =
+        sqlx::query_scalar("SELECT state FROM milk_buckets WHERE bucket = $1")
+            .bind(&bucket)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert_eq!(
+        (&state["algorithm"], &state["tokens"]),
+        (&"token-bucket".into(), &0.into()),
+        "{state}"
+    );
+
+    // Whatever was stored, the limits are the config's
+    let roomier = self::config(
+        r#"
+        algorithm = "token-bucket"
+        capacity = 3
+        refill = 1
+        interval-ms = 60000
+        "#,
+    );
+    let quota = store.try_acquire(&bucket, &roomier).await.unwrap();
+    assert_eq!((quota.allowed, quota.limit), (false, 3));
+
+    store.refill(&bucket).await.unwrap();
+    let quota = draw().await;
+    assert_eq!((quota.allowed, quota.remaining), (true, 1));
+}
+
+#[tokio::test]
+async fn stored_buckets_refill_over_time() {
+    let Some(pool) = pool().await else {
+        return;
+    };
+    let store = PgBuckets::new(pool);
+    let bucket = bucket();
+    let config = config(
+        r#"
+        algorithm = "token-bucket"
+        capacity = 1
+        refill = 1
+        interval-ms = 300
+        "#,
+    );
+
+    assert!(store.try_acquire(&bucket, &config).await.unwrap().allowed);
+    let quota = store.try_acquire(&bucket, &config).await.unwrap();
+    assert!(!quota.allowed);
+    assert!(quota.retry_after <= Duration::from_millis(300));
+
+    tokio::time::sleep(quota.retry_after + Duration::from_millis(50)).await;
+    assert!(store.try_acquire(&bucket, &config).await.unwrap().allowed);
+}
+
+#[tokio::test]
+async fn waiting_is_not_offered_with_a_store() {
+    let Some(pool) = pool().await else {
+        return;
+    };
+    let limits = toml::from_str(
+        r#"
+        [default]
+        algorithm = "token-bucket"
+        capacity = 1
+        refill = 1
+        interval-ms = 60000
+        "#,
+    )
+    .unwrap();
+    let limit = RateLimit::new(
+        ClientKey::PeerIp,
+        Clients::new(limits, Arc::new(SystemClock)),
+    )
+    .store(PgBuckets::new(pool));
+    // A fresh name, so that runs don't share a bucket
+    let app = Router::new()
+        .route("/milk", post(|| async { "Milk withdrawn\n" }))
+        .layer(limit.layer(&bucket()).all());
+    let milk = || {
+        let request = Request::post("/milk")
+            .header("prefer", "wait=5")
+            .body(Body::empty())
+            .unwrap();
+        app.clone().oneshot(request)
+    };
+
+    assert_eq!(milk().await.unwrap().status(), StatusCode::OK);
+    let response = milk().await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(!response.headers().contains_key("preference-applied"));
+}
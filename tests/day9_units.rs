@@ -0,0 +1,65 @@
+use shuttlings_cch24::day9::units::{Catalogue, ConversionError};
+
+fn convert(value: f64, from: &str, to: &str) -> f64 {
+    Catalogue::builtin().convert(value, from, to).unwrap()
+}
+
+fn assert_close(actual: f64, expected: f64) {
+    assert!(
+        (actual - expected).abs() <= 1e-6 * expected.abs().max(1.0),
+        "{actual} is not {expected}"
+    );
+}
+
+#[test]
+fn catalogue_is_consistent() {
+    let catalogue = Catalogue::builtin();
+    for (quantity, base) in &catalogue.quantities {
+        let unit = &catalogue.units[base];
+        assert_eq!(&unit.quantity, quantity);
+        assert_eq!((unit.factor, unit.offset), (1.0, 0.0), "{base}");
+    }
+    for (id, unit) in &catalogue.units {
+        assert!(catalogue.quantities.contains_key(&unit.quantity), "{id}");
+        for alias in &unit.aliases {
+            assert_eq!(catalogue.find(alias).unwrap().0, id, "{alias}");
+        }
+    }
+}
+
+#[test]
+fn milk_factors_are_unchanged() {
+    assert_close(convert(1.0, "liters", "gallons"), 0.26417205);
+    assert_close(convert(1.0, "litres", "imperial_pint"), 1.759754);
+}
+
+#[test]
+fn converts_volume_and_mass() {
+    assert_close(convert(1.0, "US gallon", "us-quart"), 4.0);
+    assert_close(convert(1.0, "imperial_gallon", "imperial_pints"), 8.0);
+    assert_close(convert(1.0, "us_cup", "us_fl_oz"), 8.0);
+    assert_close(convert(1.0, "imperial_pint", "imperial_fl_oz"), 20.0);
+    assert_close(convert(250.0, "mL", "l"), 0.25);
+    assert_close(convert(1.0, "stone", "lbs"), 14.0);
+    assert_close(convert(1.0, "lb", "oz"), 16.0);
+}
+
+#[test]
+fn converts_temperature() {
+    assert_close(convert(100.0, "celsius", "fahrenheit"), 212.0);
+    assert_close(convert(-40.0, "f", "c"), -40.0);
+    assert_close(convert(0.0, "kelvin", "celsius"), -273.15);
+}
+
+#[test]
+fn rejects_unknown_and_incompatible_units() {
+    let catalogue = Catalogue::builtin();
+    assert_eq!(
+        catalogue.convert(1.0, "litre", "furlong"),
+        Err(ConversionError::UnknownUnit("furlong".to_string()))
+    );
+    assert!(matches!(
+        catalogue.convert(1.0, "litre", "kg"),
+        Err(ConversionError::Incompatible { .. })
+    ));
+}